@@ -0,0 +1,7 @@
+//! Console build of the headless mode. On Windows the designer is a windowed program with
+//! no console to print to, so scripts run this instead; see `cli.rs` for the options.
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(midi_to_bar::cli::run(args));
+}
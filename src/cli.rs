@@ -0,0 +1,702 @@
+//! Headless mode: `midi_to_bar <file.mid> [options]` writes a comb without opening a window.
+//! Release builds of the designer are windowed programs on Windows and cannot print there,
+//! so the same options are also built into the `midi_to_bar_cli` console program.
+//!
+//! Every calibration and export setting from the sidebar has a flag here, with the same
+//! defaults, so a script can reproduce what was designed interactively. A saved project can
+//! be given instead of a MIDI file; its settings then replace the defaults, and any flags
+//! still override them.
+
+use crate::project::is_project_path;
+use crate::{
+    Articulation, ChordStrategy, CombSettings, DurationUnit, KerfDirection, LayoutMode,
+    LoadOptions, MidiNote, PaperSize, Project, RasterFormat, RestTreatment, StripJoint,
+    ToothPlacement, ToothProfile, TrackData, Tuning, TuningPreset, VoiceDropRule, apply_edits,
+    load_midi, merge_notes,
+};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const USAGE: &str = "\
+Usage: midi_to_bar <input.mid | project.json> [options]
+       midi_to_bar                     (no arguments: open the designer window)
+       midi_to_bar_cli <input.mid | project.json> [options]
+                                       (console program, for scripts on Windows)
+
+Output:
+  -o, --output <path>       File to write (default: input name with the format's extension)
+  -f, --format <fmt>        svg, dxf, gcode, png, tiff or pdf (default: from the output
+                            extension, else svg)
+  -t, --track <idx|name>    Track index, or a full or partial track name (default: 0).
+                            Several, separated by commas, are merged into one comb
+      --list-tracks         Print the note tracks and exit
+      --split-channels      Give each channel of a multi-channel track its own track
+      --percussion          Keep channel 10 drums when splitting channels
+      --sustain             Hold notes while the sustain or sostenuto pedal is down
+
+Timing and pitch:
+      --layout <mode>       seconds or beats (default: seconds)
+      --bpm <bpm>           Playing tempo for the beats layout (default: file tempo)
+      --speed <mm/s>        Drag speed (default: 200)
+      --a4 <hz>             Concert pitch: frequency of A4, or of the reference key of a
+                            keyboard mapping (default: 440)
+      --tuning <name|file>  equal, just, pythagorean, meantone, werckmeister, or a Scala
+                            .scl file (default: equal)
+      --kbm <file>          Scala keyboard mapping; also sets the concert pitch unless
+                            --a4 comes after it
+
+Chords and lanes:
+      --chord <rule>        highest, lowest, first, recent or arpeggiate (default: highest)
+      --arpeggio-ms <ms>    Slice length when arpeggiating, at least 10 (default: 60)
+      --lanes <n>           One comb row per voice, at most n rows
+      --drop <rule>         Voices dropped past the lane cap: lowest, highest, shortest
+                            or latest (default: lowest)
+      --lane-gap <mm>       Blank stock between rows (default: 2)
+
+Teeth:
+      --profile <shape>     square, triangular, sawtooth or rounded (default: square)
+      --duty <ratio>        Tooth width / pitch (default: 0.5)
+      --depth <mm>          Tooth depth (default: 8)
+      --spine <mm>          Spine height (default: 4)
+      --placement <mode>    Where each segment's teeth start: grid, continuous (carry the
+                            phase over from the segment before) or note-start
+                            (default: grid)
+
+Rests:
+      --rests <kind>        plain, damper (a bar up to the tooth tips) or notches
+                            (default: plain)
+      --min-rest <ms>       Fill shorter rests by holding the note before (default: 0)
+      --articulation <kind> How a repeated note is re-struck: legato, gap or shift
+                            (default: legato)
+      --gap-ms <ms>         Articulation gap length (default: 30)
+      --gap-beats <beats>   Articulation gap length as a fraction of a beat
+
+Laser (SVG/DXF):
+      --kerf <mm>           Kerf width (default: 0)
+      --kerf-inward         Offset the path inside the outline instead of outside
+      --min-feature <mm>    Warn about teeth or gaps narrower than this (default: 0.2)
+
+Strips:
+      --max-strip <mm>      Split the comb into pieces no longer than this, joined end to
+                            end (default: 0, one piece)
+      --joint <kind>        Connector between pieces: dovetail or pin (default: dovetail)
+
+Nesting (SVG/DXF):
+      --nest                Pack every strip onto cutting sheets instead of one comb
+      --sheet <W>x<H>       Sheet size in mm (default: 600x300)
+      --spacing <mm>        Stock between strips and around the edge (default: 3)
+      --merge-tracks        Nest one merged comb instead of one comb per track
+
+Bitmap (PNG/TIFF):
+      --dpi <dpi>           Resolution (default: 600)
+      --one-bit             Pure black and white, one bit per pixel
+      --crop-marks          Corner marks around the comb for trimming
+
+Printout (PDF):
+      --paper <size>        a4 or letter (default: a4)
+      --portrait            Short edge horizontal (default: landscape)
+      --overlap <mm>        Comb repeated on neighbouring pages (default: 10)
+
+CNC (G-code):
+      --tool <mm>           End mill diameter (default: 0.5)
+      --feed <mm/min>       Feed rate (default: 300)
+      --plunge-rate <mm/min> Plunge rate (default: 60)
+      --cut-depth <mm>      Total slot depth (default: 1.5)
+      --passes <n>          Step-down passes (default: 3)
+      --safe-z <mm>         Travel height (default: 5)
+      --rpm <rpm>           Spindle speed (default: 12000)
+
+  -h, --help                Show this help
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Svg,
+    Dxf,
+    Gcode,
+    Raster(RasterFormat),
+    Pdf,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Dxf => "dxf",
+            Format::Gcode => "nc",
+            Format::Raster(format) => format.extension(),
+            Format::Pdf => "pdf",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "svg" => Some(Format::Svg),
+            "dxf" => Some(Format::Dxf),
+            "gcode" | "nc" | "ngc" | "gc" => Some(Format::Gcode),
+            "png" => Some(Format::Raster(RasterFormat::Png)),
+            "tif" | "tiff" => Some(Format::Raster(RasterFormat::Tiff)),
+            "pdf" => Some(Format::Pdf),
+            _ => None,
+        }
+    }
+}
+
+/// Runs the command line and returns the process exit code.
+pub fn run(args: Vec<String>) -> i32 {
+    match execute(args) {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("error: {message}");
+            eprintln!("Run with --help for usage.");
+            1
+        }
+    }
+}
+
+fn value<T: FromStr>(args: &[String], i: &mut usize, flag: &str) -> Result<T, String> {
+    *i += 1;
+    let raw = args
+        .get(*i)
+        .ok_or_else(|| format!("{flag} needs a value"))?;
+    raw.parse()
+        .map_err(|_| format!("invalid value for {flag}: {raw}"))
+}
+
+fn choice<T: Copy>(
+    args: &[String],
+    i: &mut usize,
+    flag: &str,
+    options: &[(&str, T)],
+) -> Result<T, String> {
+    let raw: String = value(args, i, flag)?;
+    options
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&raw))
+        .map(|&(_, v)| v)
+        .ok_or_else(|| {
+            let names: Vec<&str> = options.iter().map(|(n, _)| *n).collect();
+            format!("{flag} must be one of {}, not {raw}", names.join(", "))
+        })
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+}
+
+/// Everything on the command line that is not a comb setting.
+#[derive(Default)]
+struct Options {
+    help: bool,
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    format: Option<Format>,
+    track: Option<String>,
+    bpm: Option<f32>,
+    list_tracks: bool,
+    nest: bool,
+    load_options: LoadOptions,
+}
+
+/// Applies the setting flags to `settings` and returns the rest.
+fn parse(args: &[String], settings: &mut CombSettings) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-h" | "--help" => {
+                options.help = true;
+                return Ok(options);
+            }
+            "-o" | "--output" => options.output = Some(value(args, &mut i, arg)?),
+            "-f" | "--format" => {
+                let name: String = value(args, &mut i, arg)?;
+                options.format =
+                    Some(Format::from_name(&name).ok_or_else(|| format!("unknown format {name}"))?);
+            }
+            "-t" | "--track" => options.track = Some(value(args, &mut i, arg)?),
+            "--list-tracks" => options.list_tracks = true,
+            "--split-channels" => options.load_options.split_channels = true,
+            "--percussion" => options.load_options.include_percussion = true,
+            "--sustain" => options.load_options.sustain_pedal = true,
+            "--layout" => {
+                settings.layout_mode = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("seconds", LayoutMode::Seconds),
+                        ("beats", LayoutMode::Beats),
+                    ],
+                )?
+            }
+            "--bpm" => options.bpm = Some(value(args, &mut i, arg)?),
+            "--speed" => settings.drag_speed = value(args, &mut i, arg)?,
+            "--a4" => settings.concert_pitch = value(args, &mut i, arg)?,
+            "--tuning" => {
+                let name: String = value(args, &mut i, arg)?;
+                let preset = match name.to_ascii_lowercase().as_str() {
+                    "equal" => Some(TuningPreset::EqualTemperament),
+                    "just" => Some(TuningPreset::JustIntonation),
+                    "pythagorean" => Some(TuningPreset::Pythagorean),
+                    "meantone" => Some(TuningPreset::QuarterCommaMeantone),
+                    "werckmeister" => Some(TuningPreset::WerckmeisterIII),
+                    _ => None,
+                };
+                match preset {
+                    Some(preset) => settings.tuning = preset.tuning(),
+                    None => {
+                        let path = PathBuf::from(&name);
+                        let scale = Tuning::load_scl(&path)
+                            .map_err(|e| format!("could not load scale {name}: {e}"))?;
+                        settings.tuning.set_scale(&stem(&path), scale);
+                    }
+                }
+            }
+            "--kbm" => {
+                let path: PathBuf = value(args, &mut i, arg)?;
+                let mapping = Tuning::load_kbm(&path)
+                    .map_err(|e| format!("could not load mapping {}: {e}", path.display()))?;
+                settings.concert_pitch = mapping.reference_frequency as f32;
+                settings.tuning.set_mapping(&stem(&path), mapping);
+            }
+            "--chord" => {
+                settings.chord_strategy = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("highest", ChordStrategy::Highest),
+                        ("lowest", ChordStrategy::Lowest),
+                        ("first", ChordStrategy::FirstOnset),
+                        ("recent", ChordStrategy::MostRecent),
+                        ("arpeggiate", ChordStrategy::Arpeggiate),
+                    ],
+                )?
+            }
+            "--arpeggio-ms" => {
+                let ms: f32 = value(args, &mut i, arg)?;
+                settings.arpeggio_slice_ms = ms.max(CombSettings::MIN_ARPEGGIO_SLICE_MS);
+            }
+            "--lanes" => {
+                settings.multi_lane = true;
+                settings.max_lanes = value(args, &mut i, arg)?;
+            }
+            "--drop" => {
+                settings.voice_drop_rule = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("lowest", VoiceDropRule::Lowest),
+                        ("highest", VoiceDropRule::Highest),
+                        ("shortest", VoiceDropRule::Shortest),
+                        ("latest", VoiceDropRule::Latest),
+                    ],
+                )?
+            }
+            "--lane-gap" => settings.lane_gap = value(args, &mut i, arg)?,
+            "--profile" => {
+                settings.tooth_shape.profile = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("square", ToothProfile::Square),
+                        ("triangular", ToothProfile::Triangular),
+                        ("sawtooth", ToothProfile::Sawtooth),
+                        ("rounded", ToothProfile::Rounded),
+                    ],
+                )?
+            }
+            "--duty" => settings.tooth_shape.duty_cycle = value(args, &mut i, arg)?,
+            "--depth" => settings.tooth_shape.depth = value(args, &mut i, arg)?,
+            "--spine" => settings.tooth_shape.spine = value(args, &mut i, arg)?,
+            "--placement" => {
+                settings.tooth_placement = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("grid", ToothPlacement::Grid),
+                        ("continuous", ToothPlacement::PhaseContinuous),
+                        ("note-start", ToothPlacement::NoteStart),
+                    ],
+                )?
+            }
+            "--rests" => {
+                settings.rest_treatment = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("plain", RestTreatment::PlainSpine),
+                        ("damper", RestTreatment::DamperBar),
+                        ("notches", RestTreatment::Notches),
+                    ],
+                )?
+            }
+            "--min-rest" => settings.min_rest_ms = value(args, &mut i, arg)?,
+            "--articulation" => {
+                settings.articulation = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[
+                        ("legato", Articulation::Legato),
+                        ("gap", Articulation::Gap),
+                        ("shift", Articulation::ShiftedTooth),
+                    ],
+                )?
+            }
+            "--gap-ms" => {
+                settings.articulation_length = value(args, &mut i, arg)?;
+                settings.articulation_unit = DurationUnit::Milliseconds;
+            }
+            "--gap-beats" => {
+                settings.articulation_length = value(args, &mut i, arg)?;
+                settings.articulation_unit = DurationUnit::Beats;
+            }
+            "--kerf" => settings.kerf = value(args, &mut i, arg)?,
+            "--kerf-inward" => settings.kerf_direction = KerfDirection::Inward,
+            "--min-feature" => settings.min_feature_width = value(args, &mut i, arg)?,
+            "--max-strip" => settings.max_strip_length = value(args, &mut i, arg)?,
+            "--joint" => {
+                settings.strip_joint = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[("dovetail", StripJoint::Dovetail), ("pin", StripJoint::Pin)],
+                )?
+            }
+            "--nest" => options.nest = true,
+            "--sheet" => {
+                let size: String = value(args, &mut i, arg)?;
+                let (width, height) = size
+                    .split_once(['x', 'X'])
+                    .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+                    .ok_or_else(|| format!("{arg} expects a size like 600x300, got {size}"))?;
+                settings.nest_settings.sheet_width = width;
+                settings.nest_settings.sheet_height = height;
+            }
+            "--spacing" => settings.nest_settings.spacing = value(args, &mut i, arg)?,
+            "--merge-tracks" => settings.nest_settings.per_track = false,
+            "--dpi" => settings.raster_settings.dpi = value(args, &mut i, arg)?,
+            "--one-bit" => settings.raster_settings.one_bit = true,
+            "--crop-marks" => settings.raster_settings.crop_marks = true,
+            "--paper" => {
+                settings.pdf_settings.paper = choice(
+                    args,
+                    &mut i,
+                    arg,
+                    &[("a4", PaperSize::A4), ("letter", PaperSize::Letter)],
+                )?
+            }
+            "--portrait" => settings.pdf_settings.landscape = false,
+            "--overlap" => settings.pdf_settings.overlap = value(args, &mut i, arg)?,
+            "--tool" => settings.gcode_settings.tool_diameter = value(args, &mut i, arg)?,
+            "--feed" => settings.gcode_settings.feed_rate = value(args, &mut i, arg)?,
+            "--plunge-rate" => settings.gcode_settings.plunge_rate = value(args, &mut i, arg)?,
+            "--cut-depth" => settings.gcode_settings.cut_depth = value(args, &mut i, arg)?,
+            "--passes" => settings.gcode_settings.passes = value(args, &mut i, arg)?,
+            "--safe-z" => settings.gcode_settings.safe_z = value(args, &mut i, arg)?,
+            "--rpm" => settings.gcode_settings.spindle_rpm = value(args, &mut i, arg)?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+            _ if options.input.is_none() => options.input = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+        i += 1;
+    }
+    Ok(options)
+}
+
+/// The format asked for, else the one the output file's extension names, else SVG.
+fn output_format(format: Option<Format>, output: Option<&Path>) -> Format {
+    format
+        .or_else(|| {
+            output
+                .and_then(|p| p.extension())
+                .and_then(|e| Format::from_name(&e.to_string_lossy()))
+        })
+        .unwrap_or(Format::Svg)
+}
+
+/// Tracks named by `--track`: a comma-separated list of indices or names. A name matches
+/// a whole track name first, then any track name containing it, ignoring case.
+fn find_tracks(tracks: &[TrackData], wanted: &str) -> Result<Vec<usize>, String> {
+    let find = |wanted: &str| -> Result<usize, String> {
+        match wanted.parse::<usize>() {
+            Ok(index) if index < tracks.len() => Ok(index),
+            Ok(index) => Err(format!(
+                "track {index} does not exist ({} tracks)",
+                tracks.len()
+            )),
+            Err(_) => {
+                let lower = wanted.to_lowercase();
+                tracks
+                    .iter()
+                    .position(|t| t.name.to_lowercase() == lower)
+                    .or_else(|| {
+                        tracks
+                            .iter()
+                            .position(|t| t.name.to_lowercase().contains(&lower))
+                    })
+                    .ok_or_else(|| format!("no track named {wanted}"))
+            }
+        }
+    };
+    wanted.split(',').map(|w| find(w.trim())).collect()
+}
+
+fn execute(args: Vec<String>) -> Result<(), String> {
+    let mut settings = CombSettings::default();
+    let Options {
+        help,
+        input,
+        output,
+        format,
+        track,
+        bpm,
+        list_tracks,
+        nest,
+        load_options,
+    } = parse(&args, &mut settings)?;
+    if help {
+        print!("{USAGE}");
+        return Ok(());
+    }
+
+    let input = input.ok_or("no input MIDI or project file given")?;
+    let (song, edits, project_track) = if is_project_path(&input) {
+        let mut project = Project::load(&input)
+            .map_err(|e| format!("could not open {}: {e}", input.display()))?;
+        project.load_options.split_channels |= load_options.split_channels;
+        project.load_options.include_percussion |= load_options.include_percussion;
+        project.load_options.sustain_pedal |= load_options.sustain_pedal;
+        let song = project
+            .source_song(&input)
+            .map_err(|e| format!("{}: {e}", input.display()))?;
+        let project_tracks = project.comb_tracks();
+        settings = project.settings;
+        parse(&args, &mut settings)?;
+        (song, project.edits, Some(project_tracks))
+    } else {
+        let song = load_midi(&input, &load_options)
+            .map_err(|e| format!("could not read {}: {e}", input.display()))?;
+        (song, Vec::new(), None)
+    };
+    for warning in &song.warnings {
+        eprintln!("warning: {warning}");
+    }
+    let tracks = song.tracks;
+
+    if list_tracks {
+        for (i, track) in tracks.iter().enumerate() {
+            println!("{i}: {} ({} notes)", track.name, track.notes.len());
+        }
+        return Ok(());
+    }
+    if tracks.is_empty() {
+        return Err(format!("{} has no tracks with notes", input.display()));
+    }
+
+    let selected: Vec<usize> = match (&track, &project_track) {
+        (Some(wanted), _) => find_tracks(&tracks, wanted)?,
+        (None, Some(saved)) => saved
+            .iter()
+            .copied()
+            .filter(|&t| t < tracks.len())
+            .collect(),
+        (None, None) => vec![0],
+    };
+    let selected = if selected.is_empty() {
+        vec![0]
+    } else {
+        selected
+    };
+    // A project keeps the tempo it was designed at; a bare MIDI file plays at its own
+    match (bpm, project_track) {
+        (Some(bpm), _) => settings.playing_bpm = bpm,
+        (None, None) => settings.playing_bpm = song.initial_bpm,
+        (None, Some(_)) => {}
+    }
+    let parts: Vec<Vec<MidiNote>> = selected
+        .iter()
+        .map(|&t| apply_edits(&tracks[t].notes, t, &edits))
+        .collect();
+    let notes = &merge_notes(parts.iter().map(Vec::as_slice));
+    if let Some(warning) = settings.silent_key_warning(notes) {
+        eprintln!("warning: {warning}");
+    }
+
+    let format = output_format(format, output.as_deref());
+    let output = output.unwrap_or_else(|| input.with_extension(format.extension()));
+    if nest && !matches!(format, Format::Svg | Format::Dxf) {
+        return Err("--nest only applies to SVG and DXF output".to_string());
+    }
+    let nesting = if nest {
+        let combs = if settings.nest_settings.per_track {
+            parts.clone()
+        } else {
+            vec![notes.clone()]
+        };
+        Some(settings.nest_combs(&combs).map_err(|e| e.to_string())?)
+    } else {
+        None
+    };
+
+    let content = match format {
+        Format::Svg | Format::Dxf if let Some(nesting) = &nesting => {
+            if format == Format::Svg {
+                settings.generate_nested_svg(nesting).into_bytes()
+            } else {
+                settings.generate_nested_dxf(nesting).into_bytes()
+            }
+        }
+        Format::Svg => settings
+            .generate_svg(notes)
+            .map_err(|e| e.to_string())?
+            .into_bytes(),
+        Format::Dxf => settings
+            .generate_dxf(notes)
+            .map_err(|e| e.to_string())?
+            .into_bytes(),
+        Format::Gcode => settings
+            .generate_gcode(notes)
+            .map_err(|e| e.to_string())?
+            .into_bytes(),
+        Format::Raster(format) => settings
+            .generate_raster(notes, format)
+            .map_err(|e| e.to_string())?,
+        Format::Pdf => {
+            let names: Vec<String> = selected.iter().map(|&t| tracks[t].name.clone()).collect();
+            settings
+                .generate_pdf(notes, &stem(&input), &names)
+                .map_err(|e| e.to_string())?
+        }
+    };
+    std::fs::write(&output, content)
+        .map_err(|e| format!("could not write {}: {e}", output.display()))?;
+
+    if format != Format::Gcode
+        && let Some(warning) = settings
+            .comb_layout(notes)
+            .and_then(|l| settings.kerf_warning(&l))
+    {
+        eprintln!("{warning}");
+    }
+    let sources: Vec<String> = selected
+        .iter()
+        .map(|&t| format!("{t} ({})", tracks[t].name))
+        .collect();
+    println!(
+        "Wrote {} from track {}",
+        output.display(),
+        sources.join(" + ")
+    );
+    if let Some(nesting) = &nesting {
+        println!("{}", nesting.report());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn parse_line(line: &str) -> Result<(Options, CombSettings), String> {
+        let mut settings = CombSettings::default();
+        parse(&args(line), &mut settings).map(|options| (options, settings))
+    }
+
+    #[test]
+    fn flags_set_options_and_settings() {
+        let (options, settings) = parse_line(
+            "song.mid --speed 150 --chord Arpeggiate --arpeggio-ms 2 --max-strip 400 \
+             -o out.dxf --track Piano,2 --split-channels --nest --sheet 600x400",
+        )
+        .unwrap();
+        assert_eq!(options.input, Some(PathBuf::from("song.mid")));
+        assert_eq!(options.output, Some(PathBuf::from("out.dxf")));
+        assert_eq!(options.track.as_deref(), Some("Piano,2"));
+        assert_eq!(options.format, None);
+        assert!(options.load_options.split_channels && options.nest && !options.help);
+        assert_eq!(settings.drag_speed, 150.0);
+        assert_eq!(settings.chord_strategy, ChordStrategy::Arpeggiate);
+        assert_eq!(
+            settings.arpeggio_slice_ms,
+            CombSettings::MIN_ARPEGGIO_SLICE_MS
+        );
+        assert_eq!(settings.max_strip_length, 400.0);
+        assert_eq!(
+            (
+                settings.nest_settings.sheet_width,
+                settings.nest_settings.sheet_height
+            ),
+            (600.0, 400.0)
+        );
+
+        let (options, settings) = parse_line("song.mid -f TIFF --dpi 300").unwrap();
+        assert_eq!(options.format, Some(Format::Raster(RasterFormat::Tiff)));
+        assert_eq!(settings.raster_settings.dpi, 300);
+        assert!(parse_line("--help --bogus").unwrap().0.help);
+    }
+
+    #[test]
+    fn bad_flags_are_reported() {
+        for (line, error) in [
+            ("song.mid --speed", "--speed needs a value"),
+            ("song.mid --speed fast", "invalid value for --speed: fast"),
+            (
+                "song.mid --drop loudest",
+                "--drop must be one of lowest, highest, shortest, latest, not loudest",
+            ),
+            ("song.mid -f doc", "unknown format doc"),
+            ("song.mid --bogus", "unknown option --bogus"),
+            ("song.mid other.mid", "unexpected argument other.mid"),
+        ] {
+            assert_eq!(parse_line(line).err().as_deref(), Some(error), "{line}");
+        }
+    }
+
+    #[test]
+    fn format_follows_the_output_extension() {
+        let format = |output: &str| output_format(None, Some(Path::new(output)));
+        assert_eq!(format("comb.SVG"), Format::Svg);
+        assert_eq!(format("comb.dxf"), Format::Dxf);
+        assert_eq!(format("comb.ngc"), Format::Gcode);
+        assert_eq!(format("comb.tiff"), Format::Raster(RasterFormat::Tiff));
+        assert_eq!(format("comb.pdf"), Format::Pdf);
+        assert_eq!(format("comb.txt"), Format::Svg);
+        assert_eq!(output_format(None, None), Format::Svg);
+        assert_eq!(
+            output_format(Some(Format::Pdf), Some(Path::new("comb.png"))),
+            Format::Pdf
+        );
+    }
+
+    #[test]
+    fn tracks_are_found_by_index_or_name() {
+        let tracks: Vec<TrackData> = ["Piano", "Grand Piano Left", "Strings"]
+            .into_iter()
+            .map(|name| TrackData::new(name.to_string(), Vec::new()))
+            .collect();
+        let find = |wanted: &str| find_tracks(&tracks, wanted);
+        assert_eq!(find("1"), Ok(vec![1]));
+        // A whole name wins over one that contains it
+        assert_eq!(find("PIANO"), Ok(vec![0]));
+        assert_eq!(find("left"), Ok(vec![1]));
+        assert_eq!(find("str, 0,1"), Ok(vec![2, 0, 1]));
+        assert_eq!(
+            find("3"),
+            Err("track 3 does not exist (3 tracks)".to_string())
+        );
+        assert_eq!(find("2,flute"), Err("no track named flute".to_string()));
+    }
+}
@@ -0,0 +1,1426 @@
+//! Comb design: tooth spacing physics, chord reduction, lane packing and layout.
+
+use crate::gcode::GcodeSettings;
+use crate::geometry::{self, RestTreatment, StripJoint, Tooth, ToothProfile, ToothShape};
+use crate::midi::{BendPoint, MidiNote, midi_pitch_to_name};
+use crate::nest::NestSettings;
+use crate::pdf::PdfSettings;
+use crate::raster::RasterSettings;
+use crate::tuning::Tuning;
+use serde::{Deserialize, Serialize};
+
+/// How the comb length maps to musical time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum LayoutMode {
+    /// Constant drag speed in real time: tempo changes stretch or squeeze the comb.
+    Seconds,
+    /// Constant drag speed per beat: every beat gets the same length, tempo is ignored.
+    Beats,
+}
+
+impl LayoutMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            LayoutMode::Seconds => "Constant speed (seconds)",
+            LayoutMode::Beats => "Constant speed (beats)",
+        }
+    }
+}
+
+/// Where the teeth of a segment start relative to the segment before it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ToothPlacement {
+    /// Teeth sit on a grid of their own spacing measured from the song start, leaving an
+    /// arbitrary interval at every pitch change.
+    Grid,
+    /// Each segment picks up the vibration where the one before it left off, so pitch
+    /// changes are seamless.
+    PhaseContinuous,
+    /// Each note starts with a tooth on its first instant, and keeps that phase wherever
+    /// it shows through a chord later on.
+    NoteStart,
+}
+
+impl ToothPlacement {
+    pub const ALL: [ToothPlacement; 3] = [
+        ToothPlacement::Grid,
+        ToothPlacement::PhaseContinuous,
+        ToothPlacement::NoteStart,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ToothPlacement::Grid => "Global grid",
+            ToothPlacement::PhaseContinuous => "Phase-continuous",
+            ToothPlacement::NoteStart => "Tooth at each note start",
+        }
+    }
+}
+
+/// How a note struck again at the pitch the comb is already playing is made audible.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Articulation {
+    /// Teeth run straight on, so the repeated notes sound as one long note.
+    Legato,
+    /// A short toothless gap at each repeated onset.
+    Gap,
+    /// The first tooth of each repeated note is shifted half a pitch, restarting the tone.
+    ShiftedTooth,
+}
+
+impl Articulation {
+    pub const ALL: [Articulation; 3] = [
+        Articulation::Legato,
+        Articulation::Gap,
+        Articulation::ShiftedTooth,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Articulation::Legato => "Legato (no break)",
+            Articulation::Gap => "Toothless gap",
+            Articulation::ShiftedTooth => "Phase-shifted tooth",
+        }
+    }
+}
+
+/// Unit of a short musical duration set by the user.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum DurationUnit {
+    Milliseconds,
+    /// A fraction of a beat, at the tempo of the note it applies to.
+    Beats,
+}
+
+impl DurationUnit {
+    pub fn label(self) -> &'static str {
+        match self {
+            DurationUnit::Milliseconds => "ms",
+            DurationUnit::Beats => "beats",
+        }
+    }
+}
+
+/// Which side of the comb outline the laser path moves to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum KerfDirection {
+    /// Path outside the part, so the finished comb keeps its nominal size.
+    Outward,
+    /// Path inside the part, for when the offcut is the piece being kept.
+    Inward,
+}
+
+impl KerfDirection {
+    pub fn label(self) -> &'static str {
+        match self {
+            KerfDirection::Outward => "Outward (keep comb size)",
+            KerfDirection::Inward => "Inward",
+        }
+    }
+}
+
+/// Rule for picking the single pitch a comb plays while several notes sound at once.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ChordStrategy {
+    /// Highest sounding note, which is usually the melody.
+    Highest,
+    /// Lowest sounding note, for bass lines.
+    Lowest,
+    /// The note that started first keeps the comb until it ends.
+    FirstOnset,
+    /// Each new note takes over the comb as soon as it starts.
+    MostRecent,
+    /// Cycle through the chord tones in short time slices.
+    Arpeggiate,
+}
+
+impl ChordStrategy {
+    pub const ALL: [ChordStrategy; 5] = [
+        ChordStrategy::Highest,
+        ChordStrategy::Lowest,
+        ChordStrategy::FirstOnset,
+        ChordStrategy::MostRecent,
+        ChordStrategy::Arpeggiate,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChordStrategy::Highest => "Highest note (melody)",
+            ChordStrategy::Lowest => "Lowest note (bass)",
+            ChordStrategy::FirstOnset => "First onset priority",
+            ChordStrategy::MostRecent => "Most recent onset",
+            ChordStrategy::Arpeggiate => "Arpeggiate",
+        }
+    }
+}
+
+/// Which voices are dropped when more notes overlap than there are lanes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum VoiceDropRule {
+    Lowest,
+    Highest,
+    Shortest,
+    Latest,
+}
+
+impl VoiceDropRule {
+    pub const ALL: [VoiceDropRule; 4] = [
+        VoiceDropRule::Lowest,
+        VoiceDropRule::Highest,
+        VoiceDropRule::Shortest,
+        VoiceDropRule::Latest,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            VoiceDropRule::Lowest => "Drop lowest voices",
+            VoiceDropRule::Highest => "Drop highest voices",
+            VoiceDropRule::Shortest => "Drop shortest notes",
+            VoiceDropRule::Latest => "Drop latest entries",
+        }
+    }
+}
+
+/// First-fit lane packing: each span goes to the lowest lane it doesn't overlap.
+///
+/// Spans are placed in the order given by `order`, so earlier entries win lanes first.
+/// With `max_lanes` set, spans that fit in no lane are left as `None`.
+pub fn pack_lanes(
+    spans: &[(f32, f32)],
+    order: &[usize],
+    max_lanes: Option<usize>,
+) -> Vec<Option<usize>> {
+    let mut lanes: Vec<Vec<(f32, f32)>> = Vec::new();
+    let mut assignment = vec![None; spans.len()];
+
+    for &i in order {
+        let (start, end) = spans[i];
+        // Lanes are kept sorted by start, so only the neighbours of the insertion point matter
+        let free = lanes.iter().enumerate().find_map(|(lane, spans)| {
+            let at = spans.partition_point(|&(s, _)| s < start);
+            let clear_before = at == 0 || spans[at - 1].1 <= start;
+            let clear_after = at == spans.len() || spans[at].0 >= end;
+            (clear_before && clear_after).then_some((lane, at))
+        });
+        let (lane, at) = match free {
+            Some(slot) => slot,
+            None if max_lanes.is_none_or(|max| lanes.len() < max) => {
+                lanes.push(Vec::new());
+                (lanes.len() - 1, 0)
+            }
+            None => continue,
+        };
+        lanes[lane].insert(at, (start, end));
+        assignment[i] = Some(lane);
+    }
+    assignment
+}
+
+#[derive(Clone, Debug)]
+pub struct CombSegment {
+    pub start_time: f32, // In layout units (seconds or beats, see `LayoutMode`)
+    pub end_time: f32,
+    pub spacing: f32,
+    pub pitch: u8,
+    pub onset: f32, // When the note it plays struck, which `NoteStart` teeth are phased from
+    pub attack: bool, // Re-strikes the pitch before it, so its first tooth is shifted
+    pub after_gap: bool, // Follows an articulation gap, which is not a rest
+}
+
+/// One comb row as laid out for export, in mm from the comb's left edge and top.
+#[derive(Clone, Debug)]
+pub struct LaneLayout {
+    pub top: f32,    // Tooth tips
+    pub base: f32,   // Where the teeth meet the spine
+    pub bottom: f32, // Back edge of the spine
+    pub teeth: Vec<Tooth>,
+    pub outline: Vec<geometry::Point>,
+    pub segments: Vec<(f32, f32, u8)>, // Start x, end x, pitch
+    pub rests: Vec<(f32, f32)>,        // Toothless stretches within the comb, clear of the teeth
+}
+
+/// Geometry shared by every vector exporter so they all cut the same comb.
+#[derive(Clone, Debug)]
+pub struct CombLayout {
+    pub x_offset: f32, // Where the comb starts, in mm from the song start
+    pub width: f32,
+    pub height: f32,
+    pub lanes: Vec<LaneLayout>,
+}
+
+/// Every calibration and export setting that shapes a comb.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CombSettings {
+    pub concert_pitch: f32, // Frequency of the tuning's reference key (A4 unless remapped), Hz
+    pub drag_speed: f32,    // Stylus speed along the comb, in mm/s
+    pub layout_mode: LayoutMode,
+    pub playing_bpm: f32, // Tempo the comb is played at in beats layout
+    pub chord_strategy: ChordStrategy,
+    pub arpeggio_slice_ms: f32, // Length of each chord tone when arpeggiating
+    pub multi_lane: bool,       // One comb row per simultaneous voice instead of chord reduction
+    pub max_lanes: usize,
+    pub voice_drop_rule: VoiceDropRule,
+    pub lane_gap: f32,    // Blank stock between stacked comb rows, in mm
+    pub min_rest_ms: f32, // Shorter silences are filled by holding the note before them
+    pub rest_treatment: RestTreatment,
+    pub articulation: Articulation,
+    pub articulation_length: f32, // Length of an articulation gap, in `articulation_unit`
+    pub articulation_unit: DurationUnit,
+    pub tooth_shape: ToothShape,
+    pub tooth_placement: ToothPlacement,
+    pub kerf: f32, // Width of material the laser burns away, in mm
+    pub kerf_direction: KerfDirection,
+    pub min_feature_width: f32, // Narrowest tooth or gap the cutter can reproduce, in mm
+    pub max_strip_length: f32,  // Longest piece the stock or bed takes, in mm; 0 keeps one piece
+    pub strip_joint: StripJoint,
+    pub gcode_settings: GcodeSettings,
+    pub raster_settings: RasterSettings,
+    pub pdf_settings: PdfSettings,
+    pub nest_settings: NestSettings,
+    pub tuning: Tuning,
+}
+
+impl Default for CombSettings {
+    fn default() -> Self {
+        Self {
+            concert_pitch: 440.0,
+            drag_speed: 200.0, // A slow, steady hand: C4 teeth come out ~0.76 mm apart
+            layout_mode: LayoutMode::Seconds,
+            playing_bpm: 120.0,
+            chord_strategy: ChordStrategy::Highest,
+            arpeggio_slice_ms: 60.0,
+            multi_lane: false,
+            max_lanes: 3,
+            voice_drop_rule: VoiceDropRule::Lowest,
+            lane_gap: 2.0,
+            min_rest_ms: 0.0,
+            rest_treatment: RestTreatment::PlainSpine,
+            articulation: Articulation::Legato,
+            articulation_length: 30.0,
+            articulation_unit: DurationUnit::Milliseconds,
+            tooth_shape: ToothShape {
+                profile: ToothProfile::Square,
+                duty_cycle: 0.5,
+                depth: 8.0,
+                spine: 4.0,
+            },
+            tooth_placement: ToothPlacement::Grid,
+            kerf: 0.0,
+            kerf_direction: KerfDirection::Outward,
+            min_feature_width: 0.2,
+            max_strip_length: 0.0,
+            strip_joint: StripJoint::Dovetail,
+            gcode_settings: GcodeSettings::default(),
+            raster_settings: RasterSettings::default(),
+            pdf_settings: PdfSettings::default(),
+            nest_settings: NestSettings::default(),
+            tuning: Tuning::default(),
+        }
+    }
+}
+
+impl CombSettings {
+    /// Shortest arpeggio slice, in ms. Shorter ones would cut a chord into more segments
+    /// than there are teeth to play them.
+    pub const MIN_ARPEGGIO_SLICE_MS: f32 = 10.0;
+
+    /// Start and end of a note in the units used by the current layout mode.
+    pub fn note_span(&self, note: &MidiNote) -> (f32, f32) {
+        match self.layout_mode {
+            LayoutMode::Seconds => (
+                note.start_seconds,
+                note.start_seconds + note.duration_seconds,
+            ),
+            LayoutMode::Beats => (note.start_time, note.start_time + note.duration),
+        }
+    }
+
+    /// When a pitch bend takes effect, in the units used by the current layout mode.
+    pub fn bend_time(&self, point: &BendPoint) -> f32 {
+        match self.layout_mode {
+            LayoutMode::Seconds => point.seconds,
+            LayoutMode::Beats => point.beat,
+        }
+    }
+
+    /// Millimetres of comb per layout unit (second or beat).
+    pub fn mm_per_unit(&self) -> f32 {
+        match self.layout_mode {
+            LayoutMode::Seconds => self.drag_speed,
+            LayoutMode::Beats => self.drag_speed * 60.0 / self.playing_bpm.max(1.0),
+        }
+    }
+
+    /// Layout units (seconds or beats) per second of playing time.
+    pub fn units_per_second(&self) -> f32 {
+        match self.layout_mode {
+            LayoutMode::Seconds => 1.0,
+            LayoutMode::Beats => self.playing_bpm.max(1.0) / 60.0,
+        }
+    }
+
+    /// Frequency of a possibly bent pitch, or `None` where the tuning leaves it silent.
+    pub fn pitch_to_frequency(&self, pitch: f32) -> Option<f32> {
+        let frequency = self.tuning.frequency(pitch, self.concert_pitch as f64)?;
+        Some(frequency as f32)
+    }
+
+    /// Tooth pitch in mm: the stylus must cross one tooth per period, so S = v / f.
+    pub fn calculate_spacing(&self, pitch: f32) -> Option<f32> {
+        Some(self.drag_speed / self.pitch_to_frequency(pitch)?)
+    }
+
+    /// Whether the tuning gives `note` a frequency. Notes on silent keys are left off the comb.
+    fn sounds(&self, note: &MidiNote) -> bool {
+        self.pitch_to_frequency(note.pitch as f32).is_some()
+    }
+
+    /// Reports notes on keys the keyboard mapping leaves silent, which the comb leaves out.
+    pub fn silent_key_warning(&self, notes: &[MidiNote]) -> Option<String> {
+        let silent: Vec<u8> = notes
+            .iter()
+            .filter(|n| !self.sounds(n))
+            .map(|n| n.pitch)
+            .collect();
+        let mut keys = silent.clone();
+        keys.sort_unstable();
+        keys.dedup();
+        let names: Vec<String> = keys.into_iter().map(midi_pitch_to_name).collect();
+        (!silent.is_empty()).then(|| {
+            format!(
+                "{} notes fall on keys {} leaves silent and are left out ({})",
+                silent.len(),
+                self.tuning.name,
+                names.join(", ")
+            )
+        })
+    }
+
+    /// Absolute tooth positions in mm, measured from the song start, placed as
+    /// `tooth_placement` asks.
+    ///
+    /// Whatever the placement, a segment that carries on the pitch of the one before it (a
+    /// bent note) continues that segment's tooth phase, so glides come out as smoothly
+    /// chirped teeth. A segment that re-strikes it (`attack`) shifts its first tooth by half a
+    /// pitch instead, or starts on a tooth when every note does. A note that only shows
+    /// through once a chord changes, such as a held lower note after the top one ends, keeps
+    /// the phase of its onset then.
+    pub fn tooth_positions(&self, segments: &[CombSegment]) -> Vec<Tooth> {
+        let mm_per_unit = self.mm_per_unit();
+        let mut teeth: Vec<Tooth> = Vec::new();
+        let mut previous: Option<&CombSegment> = None;
+        // Share of a tooth pitch covered since the last tooth, where the previous segment
+        // ended. Carried over segments too short to hold a tooth of their own.
+        let mut phase: Option<f32> = None;
+        for segment in segments {
+            let start_x = segment.start_time * mm_per_unit;
+            let end_x = segment.end_time * mm_per_unit;
+            let spacing = segment.spacing;
+
+            let abuts =
+                previous.is_some_and(|p| (p.end_time - segment.start_time).abs() < f32::EPSILON);
+            let same_note = abuts && previous.is_some_and(|p| p.pitch == segment.pitch);
+            let continues = match self.tooth_placement {
+                ToothPlacement::Grid => same_note,
+                ToothPlacement::PhaseContinuous => abuts,
+                ToothPlacement::NoteStart => same_note && !segment.attack,
+            };
+            previous = Some(segment);
+
+            if spacing > 0.01 {
+                let mut current_x_abs = match phase {
+                    Some(done) if continues => start_x + (1.0 - done) * spacing,
+                    _ if self.tooth_placement == ToothPlacement::NoteStart => {
+                        let onset_x = segment.onset.min(segment.start_time) * mm_per_unit;
+                        onset_x + ((start_x - onset_x) / spacing).ceil() * spacing
+                    }
+                    // Nothing to continue from after a rest, so the note starts on a tooth
+                    _ if self.tooth_placement != ToothPlacement::Grid => start_x,
+                    _ => (start_x / spacing).ceil() * spacing,
+                };
+                if segment.attack && continues {
+                    let shifted = current_x_abs - spacing / 2.0;
+                    current_x_abs = if shifted >= start_x {
+                        shifted
+                    } else {
+                        current_x_abs + spacing / 2.0
+                    };
+                }
+
+                while current_x_abs < end_x {
+                    teeth.push(Tooth {
+                        x: current_x_abs,
+                        spacing,
+                    });
+                    current_x_abs += spacing;
+                }
+                phase = Some((1.0 - (current_x_abs - end_x) / spacing).clamp(0.0, 1.0));
+            } else {
+                phase = None;
+            }
+        }
+        teeth
+    }
+
+    /// Start and end of the comb in mm, across all lanes.
+    fn comb_extent(&self, lanes: &[Vec<CombSegment>]) -> Option<(f32, f32)> {
+        let mm_per_unit = self.mm_per_unit();
+        let start = lanes
+            .iter()
+            .filter_map(|lane| lane.first())
+            .map(|s| s.start_time)
+            .reduce(f32::min)?;
+        let end = lanes
+            .iter()
+            .flatten()
+            .map(|s| s.end_time)
+            .fold(start, f32::max);
+        Some((start * mm_per_unit, end * mm_per_unit))
+    }
+
+    /// Lays out every comb row for export, or `None` when there is nothing to cut.
+    pub fn comb_layout(&self, notes: &[MidiNote]) -> Option<CombLayout> {
+        const MARGIN: f32 = 5.0; // Trailing blank stock after the last tooth, in mm
+
+        let lanes = self.get_comb_lanes(notes);
+        let (start_x, max_x) = self.comb_extent(&lanes)?;
+        let mm_per_unit = self.mm_per_unit();
+        let shape = &self.tooth_shape;
+
+        let lane_teeth: Vec<Vec<Tooth>> = lanes
+            .iter()
+            .map(|segments| {
+                self.tooth_positions(segments)
+                    .into_iter()
+                    // Use a small epsilon to avoid floating point issues at the start
+                    .filter(|t| t.x >= start_x - f32::EPSILON)
+                    .collect()
+            })
+            .collect();
+        // Blank stock ahead of the first notes, so a tooth on the very start is cut whole
+        // instead of being clipped by the comb's edge
+        let lead = lane_teeth
+            .iter()
+            .filter_map(|teeth| teeth.first())
+            .map(|t| shape.tooth_width(t.spacing) / 2.0 - (t.x - start_x))
+            .fold(0.0f32, f32::max);
+        let x_offset = start_x - lead;
+
+        let width = max_x - x_offset + MARGIN;
+        let lanes = lanes
+            .iter()
+            .zip(lane_teeth)
+            .enumerate()
+            .map(|(lane, (lane_segments, teeth))| {
+                let top = lane as f32 * (shape.row_height() + self.lane_gap);
+                let teeth: Vec<Tooth> = teeth
+                    .into_iter()
+                    .map(|t| Tooth {
+                        x: t.x - x_offset,
+                        ..t
+                    })
+                    .collect();
+                let segments: Vec<(f32, f32, u8)> = lane_segments
+                    .iter()
+                    .map(|s| {
+                        (
+                            s.start_time * mm_per_unit - x_offset,
+                            s.end_time * mm_per_unit - x_offset,
+                            s.pitch,
+                        )
+                    })
+                    .collect();
+                // Everything up to the last note of any lane that this lane doesn't play,
+                // except the gaps that articulate repeated notes
+                let mut rests = Vec::new();
+                let mut last_end = lead;
+                for (&(start, end, _), segment) in segments.iter().zip(lane_segments) {
+                    if start > last_end && !segment.after_gap {
+                        rests.push((last_end, start));
+                    }
+                    last_end = last_end.max(end);
+                }
+                if max_x - x_offset > last_end {
+                    rests.push((last_end, max_x - x_offset));
+                }
+                let rests = geometry::clear_rests(&teeth, &rests, width, shape);
+                LaneLayout {
+                    top,
+                    base: top + shape.depth,
+                    bottom: top + shape.row_height(),
+                    outline: geometry::comb_outline(
+                        &teeth,
+                        &rests,
+                        self.rest_treatment,
+                        width,
+                        top,
+                        shape,
+                    ),
+                    teeth,
+                    segments,
+                    rests,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Some(CombLayout {
+            x_offset,
+            width,
+            height: lanes.last().map_or(shape.row_height(), |l| l.bottom),
+            lanes,
+        })
+    }
+
+    /// Signed distance the cut path sits from the nominal outline (positive is outward).
+    pub fn kerf_offset(&self) -> f32 {
+        match self.kerf_direction {
+            KerfDirection::Outward => self.kerf / 2.0,
+            KerfDirection::Inward => -self.kerf / 2.0,
+        }
+    }
+
+    /// Height of text engraved on the spine, in mm.
+    pub fn label_height(&self) -> f32 {
+        (self.tooth_shape.spine * 0.4).clamp(0.5, 3.0)
+    }
+
+    /// The path the cutter follows for a lane outline, after kerf compensation.
+    pub fn cut_outline(&self, outline: &[geometry::Point]) -> Vec<geometry::Point> {
+        geometry::offset_outline(outline, self.kerf_offset())
+    }
+
+    /// Describes teeth and gaps whose kerf-compensated cut path is narrower than the
+    /// minimum feature width, or returns `None` when everything is cuttable.
+    pub fn kerf_warning(&self, layout: &CombLayout) -> Option<String> {
+        let grow = 2.0 * self.kerf_offset();
+        let min = self.min_feature_width;
+        let mut narrow_teeth = 0;
+        let mut narrow_gaps = 0;
+        let mut first_x = f32::INFINITY;
+
+        for lane in &layout.lanes {
+            for (left, right) in geometry::tooth_spans(&lane.teeth, layout.width, &self.tooth_shape)
+            {
+                if right - left + grow < min {
+                    narrow_teeth += 1;
+                    first_x = first_x.min(left);
+                }
+            }
+            let gaps = geometry::gap_spans(
+                &lane.teeth,
+                &lane.rests,
+                self.rest_treatment,
+                layout.width,
+                &self.tooth_shape,
+            );
+            // Only gaps between two teeth; the open stock at either end is never narrow
+            for &(left, right) in gaps.iter().filter(|&&(l, r)| l > 0.0 && r < layout.width) {
+                if right - left - grow < min {
+                    narrow_gaps += 1;
+                    first_x = first_x.min(left);
+                }
+            }
+        }
+
+        (narrow_teeth + narrow_gaps > 0).then(|| {
+            format!(
+                "⚠ {} teeth and {} gaps under {:.2} mm after kerf (first at {:.1} mm)",
+                narrow_teeth, narrow_gaps, min, first_x
+            )
+        })
+    }
+
+    /// One list of segments per comb row: a single row of reduced chords, or one row per voice.
+    pub fn get_comb_lanes(&self, notes: &[MidiNote]) -> Vec<Vec<CombSegment>> {
+        if !self.multi_lane {
+            return vec![self.get_comb_segments(notes)];
+        }
+
+        let notes: Vec<&MidiNote> = notes.iter().filter(|n| self.sounds(n)).collect();
+        let spans: Vec<(f32, f32)> = notes.iter().map(|n| self.note_span(n)).collect();
+        let mut order: Vec<usize> = (0..notes.len()).collect();
+        order.sort_by(|&a, &b| spans[a].0.total_cmp(&spans[b].0));
+
+        // Where more notes sound than there are lanes, the rule picks one to drop. Notes
+        // that overlap all sound at the latest of their starts, so checking at each start
+        // is enough; ties drop the later note.
+        let max_lanes = self.max_lanes.max(1);
+        let mut dropped = vec![false; notes.len()];
+        let mut sounding: Vec<usize> = Vec::new();
+        for &i in &order {
+            sounding.retain(|&k| spans[k].1 > spans[i].0);
+            sounding.push(i);
+            if sounding.len() <= max_lanes {
+                continue;
+            }
+            let latest_first = sounding.iter().rev().copied();
+            let length = |k: usize| spans[k].1 - spans[k].0;
+            let victim = match self.voice_drop_rule {
+                VoiceDropRule::Lowest => latest_first.min_by_key(|&k| notes[k].pitch),
+                VoiceDropRule::Highest => {
+                    latest_first.min_by_key(|&k| std::cmp::Reverse(notes[k].pitch))
+                }
+                VoiceDropRule::Shortest => {
+                    latest_first.min_by(|&a, &b| length(a).total_cmp(&length(b)))
+                }
+                VoiceDropRule::Latest => Some(i),
+            }
+            .unwrap_or(i);
+            dropped[victim] = true;
+            sounding.retain(|&k| k != victim);
+        }
+
+        // In start order, first-fit needs no more lanes than notes ever sound together
+        order.retain(|&i| !dropped[i]);
+        let assignment = pack_lanes(&spans, &order, Some(max_lanes));
+        let lane_count = assignment.iter().flatten().max().map_or(0, |&l| l + 1);
+        (0..lane_count)
+            .map(|lane| {
+                let lane_notes: Vec<&MidiNote> = notes
+                    .iter()
+                    .zip(&assignment)
+                    .filter(|(_, a)| **a == Some(lane))
+                    .map(|(&n, _)| n)
+                    .collect();
+                self.segments_for_notes(&lane_notes)
+            })
+            .collect()
+    }
+
+    /// The comb as a single row: one tooth spacing at a time, chords reduced by
+    /// `chord_strategy`, rests under `min_rest_ms` closed and adjacent segments of equal
+    /// spacing merged.
+    pub fn get_comb_segments(&self, notes: &[MidiNote]) -> Vec<CombSegment> {
+        let notes: Vec<&MidiNote> = notes.iter().filter(|n| self.sounds(n)).collect();
+        self.segments_for_notes(&notes)
+    }
+
+    /// Sweeps over note on/off events and reduces whatever is sounding to one spacing at a time.
+    fn segments_for_notes(&self, notes: &[&MidiNote]) -> Vec<CombSegment> {
+        if notes.is_empty() {
+            return vec![];
+        }
+
+        // A note ending as another of the same pitch starts must not silence the new one
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+        enum EventType {
+            Off,
+            On,
+            Bend,
+        }
+        struct Event {
+            time: f32,
+            kind: EventType,
+            pitch: u8,
+            /// Semitones of bend, for `Bend` events
+            bend: f32,
+        }
+        let mut events = Vec::new();
+        // Onset, pitch and articulation gap of every note
+        let mut onsets = Vec::new();
+        for note in notes {
+            let (start, end) = self.note_span(note);
+            onsets.push((start, note.pitch, self.articulation_gap(note)));
+            events.push(Event {
+                time: start,
+                kind: EventType::On,
+                pitch: note.pitch,
+                bend: 0.0,
+            });
+            for point in &note.bend {
+                let time = self.bend_time(point).max(start);
+                if time < end {
+                    events.push(Event {
+                        time,
+                        kind: EventType::Bend,
+                        pitch: note.pitch,
+                        bend: point.semitones,
+                    });
+                }
+            }
+            events.push(Event {
+                time: end,
+                kind: EventType::Off,
+                pitch: note.pitch,
+                bend: 0.0,
+            });
+        }
+        events.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.kind.cmp(&b.kind))
+        });
+
+        let mut segments = Vec::new();
+        // Sounding pitches, with the time each one started and its current bend
+        let mut active_pitches = std::collections::BTreeMap::new();
+        // How many notes hold each pitch, as overlapping notes may share one
+        let mut held = std::collections::HashMap::<u8, usize>::new();
+        let mut last_time = if events.is_empty() {
+            0.0
+        } else {
+            events[0].time
+        };
+
+        for event in &events {
+            let current_time = event.time;
+            if current_time > last_time && !active_pitches.is_empty() {
+                self.reduce_chord(&active_pitches, last_time, current_time, &mut segments);
+            }
+
+            match event.kind {
+                EventType::On => {
+                    active_pitches.insert(event.pitch, (event.time, 0.0));
+                    *held.entry(event.pitch).or_default() += 1;
+                }
+                EventType::Bend => {
+                    if let Some((_, bend)) = active_pitches.get_mut(&event.pitch) {
+                        *bend = event.bend;
+                    }
+                }
+                EventType::Off => {
+                    let count = held.entry(event.pitch).or_default();
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        active_pitches.remove(&event.pitch);
+                    }
+                }
+            }
+            last_time = current_time;
+        }
+
+        // Merge segments
+        if segments.is_empty() {
+            return vec![];
+        }
+
+        let min_rest = self.min_rest_ms / 1000.0 * self.units_per_second();
+        let mut merged = Vec::new();
+        let mut iter = segments.into_iter();
+        let mut current = iter.next().unwrap();
+
+        for next in iter {
+            // Too short a rest to be worth a silence: the note before it rings on
+            if next.start_time > current.end_time && next.start_time - current.end_time < min_rest {
+                current.end_time = next.start_time;
+            }
+            // Using an epsilon for f32 comparison
+            if (next.spacing - current.spacing).abs() < f32::EPSILON
+                && (next.start_time - current.end_time).abs() < f32::EPSILON
+            {
+                current.end_time = next.end_time;
+            } else {
+                merged.push(current);
+                current = next;
+            }
+        }
+        merged.push(current);
+
+        self.articulate(merged, &onsets)
+    }
+
+    /// Length of the articulation gap before `note`, in layout units.
+    fn articulation_gap(&self, note: &MidiNote) -> f32 {
+        match self.articulation_unit {
+            DurationUnit::Milliseconds => {
+                self.articulation_length / 1000.0 * self.units_per_second()
+            }
+            DurationUnit::Beats => match self.layout_mode {
+                LayoutMode::Beats => self.articulation_length,
+                // Seconds per beat where the note is, so the gap follows tempo changes
+                LayoutMode::Seconds if note.duration > 0.0 => {
+                    self.articulation_length * note.duration_seconds / note.duration
+                }
+                LayoutMode::Seconds => 0.0,
+            },
+        }
+    }
+
+    /// Splits segments at notes that re-strike the pitch already playing, which would
+    /// otherwise run on as one note, and marks each repeat as `articulation` asks.
+    fn articulate(
+        &self,
+        segments: Vec<CombSegment>,
+        onsets: &[(f32, u8, f32)],
+    ) -> Vec<CombSegment> {
+        if self.articulation == Articulation::Legato {
+            return segments;
+        }
+        let mut onsets = onsets.to_vec();
+        onsets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut articulated = Vec::with_capacity(segments.len());
+        for segment in segments {
+            let from = onsets.partition_point(|&(t, _, _)| t <= segment.start_time);
+            let mut piece = segment.clone();
+            for &(onset, _, gap) in onsets[from..]
+                .iter()
+                .take_while(|&&(t, _, _)| t < segment.end_time)
+                .filter(|&&(_, pitch, _)| pitch == segment.pitch)
+            {
+                if onset <= piece.start_time {
+                    continue; // Inside the previous gap
+                }
+                let (next_start, attack) = match self.articulation {
+                    Articulation::Gap => (onset + gap, false),
+                    _ => (onset, true),
+                };
+                articulated.push(CombSegment {
+                    end_time: onset,
+                    ..piece.clone()
+                });
+                // A gap's teeth start after it, where the repeat is first heard
+                piece.start_time = next_start;
+                piece.onset = next_start;
+                piece.attack = attack;
+                piece.after_gap = !attack;
+            }
+            if piece.end_time > piece.start_time {
+                articulated.push(piece);
+            }
+        }
+        articulated
+    }
+
+    /// Emits the segments covering `start..end` while `chord` (pitch -> onset and bend in
+    /// semitones) is sounding.
+    fn reduce_chord(
+        &self,
+        chord: &std::collections::BTreeMap<u8, (f32, f32)>,
+        start: f32,
+        end: f32,
+        segments: &mut Vec<CombSegment>,
+    ) {
+        // Ties on onset go to the higher pitch, which is the likelier melody note
+        let onset = |a: &f32, b: &f32| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal);
+        let tone = |(&pitch, &(onset, bend)): (&u8, &(f32, f32))| (pitch, onset, bend);
+        let pitch = match self.chord_strategy {
+            ChordStrategy::Highest => chord.iter().next_back().map(tone),
+            ChordStrategy::Lowest => chord.iter().next().map(tone),
+            ChordStrategy::FirstOnset => chord
+                .iter()
+                .min_by(|(pa, (ta, _)), (pb, (tb, _))| onset(ta, tb).then_with(|| pb.cmp(pa)))
+                .map(tone),
+            ChordStrategy::MostRecent => chord
+                .iter()
+                .max_by(|(pa, (ta, _)), (pb, (tb, _))| onset(ta, tb).then_with(|| pa.cmp(pb)))
+                .map(tone),
+            ChordStrategy::Arpeggiate => {
+                let tones: Vec<(u8, f32, f32)> = chord.iter().map(tone).collect();
+                let slice_ms = self.arpeggio_slice_ms.max(Self::MIN_ARPEGGIO_SLICE_MS);
+                let slice = slice_ms / 1000.0 * self.units_per_second();
+                if tones.len() > 1 {
+                    // Slices sit on a global grid so the cycle doesn't restart at every event
+                    let mut index = (start / slice).floor() as i64;
+                    let mut t = start;
+                    while t < end {
+                        let slice_end = ((index + 1) as f32 * slice).min(end);
+                        let (tone, onset, bend) =
+                            tones[index.rem_euclid(tones.len() as i64) as usize];
+                        // Grid rounding can put a slice end at or before `t`
+                        if slice_end > t {
+                            if let Some(spacing) = self.calculate_spacing(tone as f32 + bend) {
+                                segments.push(CombSegment {
+                                    start_time: t,
+                                    end_time: slice_end,
+                                    spacing,
+                                    pitch: tone,
+                                    onset,
+                                    attack: false,
+                                    after_gap: false,
+                                });
+                            }
+                            t = slice_end;
+                        }
+                        index += 1;
+                    }
+                    return;
+                }
+                tones.last().copied()
+            }
+        };
+
+        // A bend into a silent key is silent for as long as it stays there
+        if let Some((pitch, onset, bend)) = pitch
+            && let Some(spacing) = self.calculate_spacing(pitch as f32 + bend)
+        {
+            segments.push(CombSegment {
+                start_time: start,
+                end_time: end,
+                spacing,
+                pitch,
+                onset,
+                attack: false,
+                after_gap: false,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuning::KeyboardMapping;
+
+    fn segment(start_time: f32, end_time: f32, spacing: f32, pitch: u8) -> CombSegment {
+        CombSegment {
+            start_time,
+            end_time,
+            spacing,
+            pitch,
+            onset: start_time,
+            attack: false,
+            after_gap: false,
+        }
+    }
+
+    fn note(pitch: u8, start: f32, end: f32) -> MidiNote {
+        MidiNote {
+            pitch,
+            start_time: start * 2.0,
+            duration: (end - start) * 2.0,
+            start_seconds: start,
+            duration_seconds: end - start,
+            bend: Vec::new(),
+            soft: false,
+        }
+    }
+
+    /// Start, end and pitch of every segment of a one-row comb.
+    fn spans(settings: &CombSettings, notes: &[MidiNote]) -> Vec<(f32, f32, u8)> {
+        settings
+            .get_comb_segments(notes)
+            .iter()
+            .map(|s| (s.start_time, s.end_time, s.pitch))
+            .collect()
+    }
+
+    /// C4 held throughout, G4 from 0.5 s to 1.5 s and G3 from 1 s to 1.5 s.
+    fn chord() -> Vec<MidiNote> {
+        vec![note(60, 0.0, 2.0), note(67, 0.5, 1.5), note(55, 1.0, 1.5)]
+    }
+
+    fn strategy(chord_strategy: ChordStrategy) -> CombSettings {
+        CombSettings {
+            chord_strategy,
+            arpeggio_slice_ms: 250.0,
+            ..CombSettings::default()
+        }
+    }
+
+    #[test]
+    fn highest_plays_the_top_note() {
+        assert_eq!(
+            spans(&strategy(ChordStrategy::Highest), &chord()),
+            [(0.0, 0.5, 60), (0.5, 1.5, 67), (1.5, 2.0, 60)]
+        );
+    }
+
+    #[test]
+    fn lowest_plays_the_bass_note() {
+        assert_eq!(
+            spans(&strategy(ChordStrategy::Lowest), &chord()),
+            [(0.0, 1.0, 60), (1.0, 1.5, 55), (1.5, 2.0, 60)]
+        );
+    }
+
+    #[test]
+    fn first_onset_keeps_the_note_that_started_first() {
+        assert_eq!(
+            spans(&strategy(ChordStrategy::FirstOnset), &chord()),
+            [(0.0, 2.0, 60)]
+        );
+    }
+
+    #[test]
+    fn most_recent_follows_each_new_note() {
+        assert_eq!(
+            spans(&strategy(ChordStrategy::MostRecent), &chord()),
+            [
+                (0.0, 0.5, 60),
+                (0.5, 1.0, 67),
+                (1.0, 1.5, 55),
+                (1.5, 2.0, 60)
+            ]
+        );
+    }
+
+    #[test]
+    fn arpeggiate_cycles_the_chord_on_a_global_grid() {
+        // 0.5 s is the third slice and 1 s the fifth, so each chord starts mid-cycle
+        assert_eq!(
+            spans(&strategy(ChordStrategy::Arpeggiate), &chord()),
+            [
+                (0.0, 0.75, 60),
+                (0.75, 1.0, 67),
+                (1.0, 1.25, 60),
+                (1.25, 1.5, 67),
+                (1.5, 2.0, 60)
+            ]
+        );
+    }
+
+    #[test]
+    fn arpeggio_slices_have_a_floor_and_never_run_backwards() {
+        let settings = CombSettings {
+            chord_strategy: ChordStrategy::Arpeggiate,
+            arpeggio_slice_ms: 0.001,
+            ..CombSettings::default()
+        };
+        // Late in a long song, where the first grid line past the start rounds onto it
+        let notes = [note(60, 2107.0898, 2108.0), note(64, 2107.0898, 2108.0)];
+        let segments = settings.get_comb_segments(&notes);
+        assert!(segments.len() <= 101, "{} segments", segments.len());
+        assert!(segments.iter().all(|s| s.end_time > s.start_time));
+    }
+
+    fn lanes(max_lanes: usize, voice_drop_rule: VoiceDropRule) -> CombSettings {
+        CombSettings {
+            multi_lane: true,
+            max_lanes,
+            voice_drop_rule,
+            ..CombSettings::default()
+        }
+    }
+
+    /// Pitches each lane plays, in order.
+    fn lane_pitches(settings: &CombSettings, notes: &[MidiNote]) -> Vec<Vec<u8>> {
+        settings
+            .get_comb_lanes(notes)
+            .iter()
+            .map(|lane| lane.iter().map(|s| s.pitch).collect())
+            .collect()
+    }
+
+    #[test]
+    fn each_rule_drops_its_own_voice_past_the_cap() {
+        // All four sound from 1.5 s to 1.8 s, one more than the lanes
+        let notes = [
+            note(60, 0.0, 4.0), // Lowest
+            note(72, 0.5, 3.5), // Highest
+            note(65, 1.0, 1.8), // Shortest
+            note(67, 1.5, 3.5), // Latest
+        ];
+        for (rule, dropped) in [
+            (VoiceDropRule::Lowest, 60),
+            (VoiceDropRule::Highest, 72),
+            (VoiceDropRule::Shortest, 65),
+            (VoiceDropRule::Latest, 67),
+        ] {
+            let lanes = lane_pitches(&lanes(3, rule), &notes);
+            assert_eq!(lanes.len(), 3, "{rule:?}: {lanes:?}");
+            let mut kept: Vec<u8> = lanes.concat();
+            kept.sort();
+            let expected: Vec<u8> = [60, 65, 67, 72]
+                .into_iter()
+                .filter(|&p| p != dropped)
+                .collect();
+            assert_eq!(kept, expected, "{rule:?}");
+        }
+    }
+
+    #[test]
+    fn voices_are_only_dropped_where_too_many_sound() {
+        // Never more than two at once, but packing by pitch would want a third lane for
+        // the lowest note
+        let notes = [
+            note(80, 0.0, 2.0),
+            note(70, 1.0, 4.0),
+            note(60, 3.0, 6.0),
+            note(75, 5.0, 8.0),
+        ];
+        for rule in VoiceDropRule::ALL {
+            assert_eq!(
+                lane_pitches(&lanes(2, rule), &notes),
+                [vec![80, 60], vec![70, 75]],
+                "{rule:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn one_lane_keeps_one_voice() {
+        let notes = [note(60, 0.0, 2.0), note(64, 0.0, 2.0), note(67, 1.0, 3.0)];
+        assert_eq!(
+            lane_pitches(&lanes(1, VoiceDropRule::Lowest), &notes),
+            [vec![67]]
+        );
+        assert_eq!(
+            lane_pitches(&lanes(0, VoiceDropRule::Highest), &notes),
+            [vec![60]]
+        );
+    }
+
+    /// C4 struck twice in a row, at 100 mm/s.
+    fn repeated(articulation: Articulation) -> (CombSettings, Vec<MidiNote>) {
+        let settings = CombSettings {
+            drag_speed: 100.0,
+            articulation,
+            articulation_length: 30.0,
+            articulation_unit: DurationUnit::Milliseconds,
+            ..CombSettings::default()
+        };
+        (settings, vec![note(60, 0.0, 1.0), note(60, 1.0, 2.0)])
+    }
+
+    #[test]
+    fn legato_runs_repeated_notes_together() {
+        let (settings, notes) = repeated(Articulation::Legato);
+        assert_eq!(spans(&settings, &notes), [(0.0, 2.0, 60)]);
+    }
+
+    #[test]
+    fn gap_leaves_the_repeated_onset_toothless() {
+        let (settings, notes) = repeated(Articulation::Gap);
+        let segments = settings.get_comb_segments(&notes);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].end_time, 1.0);
+        assert!((segments[1].start_time - 1.03).abs() < 1e-5);
+        assert!(segments[1].after_gap && !segments[1].attack);
+
+        // 30 ms at 100 mm/s is 3 mm of bare spine, which is not a rest
+        let layout = settings.comb_layout(&notes).unwrap();
+        let lane = &layout.lanes[0];
+        let teeth: Vec<f32> = lane.teeth.iter().map(|t| t.x + layout.x_offset).collect();
+        let before = teeth
+            .iter()
+            .copied()
+            .filter(|&x| x < 100.0)
+            .fold(0.0, f32::max);
+        let after = teeth.iter().copied().find(|&x| x >= 100.0).unwrap();
+        assert!(before > 100.0 - lane.teeth[0].spacing, "{before}");
+        assert!(
+            after >= 103.0 && after < 103.0 + lane.teeth[0].spacing,
+            "{after}"
+        );
+        assert!(lane.rests.is_empty(), "{:?}", lane.rests);
+    }
+
+    #[test]
+    fn shifted_tooth_restarts_half_a_pitch_off() {
+        let (settings, notes) = repeated(Articulation::ShiftedTooth);
+        let segments = settings.get_comb_segments(&notes);
+        assert_eq!(segments.len(), 2);
+        assert!(segments[1].attack && !segments[1].after_gap);
+        assert_eq!((segments[1].start_time, segments[1].end_time), (1.0, 2.0));
+
+        // Teeth sit on the C4 grid until the repeat, and between its lines after it
+        let spacing = segments[0].spacing;
+        let grid_phase = |x: f32| (x / spacing).fract();
+        let teeth = settings.tooth_positions(&segments);
+        let split = teeth.partition_point(|t| t.x < 100.0);
+        for tooth in &teeth[..split] {
+            let phase = grid_phase(tooth.x);
+            assert!(phase.min(1.0 - phase) < 1e-2, "{}", tooth.x);
+        }
+        for tooth in &teeth[split..] {
+            assert!((grid_phase(tooth.x) - 0.5).abs() < 1e-2, "{}", tooth.x);
+        }
+        assert!(teeth[split].x - 100.0 < spacing);
+    }
+
+    #[test]
+    fn rests_shorter_than_the_minimum_are_filled() {
+        let notes = [note(60, 0.0, 1.0), note(62, 1.05, 2.0), note(64, 2.5, 3.0)];
+        let settings = CombSettings {
+            min_rest_ms: 100.0,
+            ..CombSettings::default()
+        };
+        // The 50 ms rest goes to the note before it; the 500 ms one stays
+        assert_eq!(
+            spans(&settings, &notes),
+            [(0.0, 1.05, 60), (1.05, 2.0, 62), (2.5, 3.0, 64)]
+        );
+        assert_eq!(
+            spans(&CombSettings::default(), &notes),
+            [(0.0, 1.0, 60), (1.05, 2.0, 62), (2.5, 3.0, 64)]
+        );
+    }
+
+    #[test]
+    fn rests_between_notes_are_cut_as_treated() {
+        let notes = [note(60, 0.0, 1.0), note(60, 2.0, 3.0)];
+        for treatment in RestTreatment::ALL {
+            let settings = CombSettings {
+                drag_speed: 10.0,
+                rest_treatment: treatment,
+                ..CombSettings::default()
+            };
+            let layout = settings.comb_layout(&notes).unwrap();
+            let lane = &layout.lanes[0];
+            // The rest is the 10 mm between the notes, less the teeth reaching into it
+            let [(left, right)] = lane.rests[..] else {
+                panic!("{treatment:?}: {:?}", lane.rests);
+            };
+            assert!(left >= 10.0 - layout.x_offset && right <= 20.0 - layout.x_offset);
+            assert!(right - left > 9.9, "{treatment:?}: {left}..{right}");
+
+            let in_rest = |&&(x, _): &&geometry::Point| x > left + 1e-3 && x < right - 1e-3;
+            let inside: Vec<&geometry::Point> = lane.outline.iter().filter(in_rest).collect();
+            match treatment {
+                RestTreatment::PlainSpine => assert!(inside.is_empty(), "{inside:?}"),
+                // A bar at full tooth height, or a notch into the spine, mid-rest
+                RestTreatment::DamperBar => {
+                    let corners = lane.outline.iter().filter(|p| p.1 == lane.top);
+                    assert_eq!(corners.filter(|p| p.0 == left || p.0 == right).count(), 2);
+                }
+                RestTreatment::Notches => {
+                    let depth = lane.base + settings.tooth_shape.spine / 2.0;
+                    let notch: Vec<f32> = inside
+                        .iter()
+                        .filter(|p| p.1 == depth)
+                        .map(|p| p.0)
+                        .collect();
+                    let middle = (left + right) / 2.0;
+                    assert_eq!(notch, [middle - 0.5, middle + 0.5]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kerf_warning_counts_features_the_cut_closes_up() {
+        // Teeth 0.6 mm wide at 2 and 3 mm leave 0.4 mm between them
+        let layout = CombLayout {
+            x_offset: 0.0,
+            width: 5.0,
+            height: 12.0,
+            lanes: vec![LaneLayout {
+                top: 0.0,
+                base: 8.0,
+                bottom: 12.0,
+                teeth: vec![
+                    Tooth {
+                        x: 2.0,
+                        spacing: 1.0,
+                    },
+                    Tooth {
+                        x: 3.0,
+                        spacing: 1.0,
+                    },
+                ],
+                outline: Vec::new(),
+                segments: vec![(0.0, 5.0, 60)],
+                rests: Vec::new(),
+            }],
+        };
+        let warning = |kerf: f32, kerf_direction: KerfDirection| {
+            let mut settings = CombSettings {
+                kerf,
+                kerf_direction,
+                ..CombSettings::default()
+            };
+            settings.tooth_shape.duty_cycle = 0.6;
+            settings.kerf_warning(&layout)
+        };
+
+        assert_eq!(warning(0.0, KerfDirection::Outward), None);
+        // Growing the teeth by 0.3 mm leaves a 0.1 mm gap; the open ends never count
+        assert_eq!(
+            warning(0.3, KerfDirection::Outward).as_deref(),
+            Some("⚠ 0 teeth and 1 gaps under 0.20 mm after kerf (first at 2.3 mm)")
+        );
+        // Shrinking them by 0.5 mm leaves 0.1 mm teeth
+        assert_eq!(
+            warning(0.5, KerfDirection::Inward).as_deref(),
+            Some("⚠ 2 teeth and 0 gaps under 0.20 mm after kerf (first at 1.7 mm)")
+        );
+    }
+
+    #[test]
+    fn phase_carries_over_segments_too_short_for_a_tooth() {
+        let settings = CombSettings {
+            drag_speed: 1.0, // One mm per second, so times are positions
+            tooth_placement: ToothPlacement::PhaseContinuous,
+            ..CombSettings::default()
+        };
+        // The middle segment ends 0.85 of its pitch past the tooth at 0 without a tooth of
+        // its own, so the next one follows 0.15 of a pitch into the last segment
+        let segments = [
+            segment(0.0, 0.5, 1.0, 60),
+            segment(0.5, 1.2, 2.0, 62),
+            segment(1.2, 3.0, 1.0, 64),
+        ];
+        let teeth: Vec<f32> = settings
+            .tooth_positions(&segments)
+            .iter()
+            .map(|t| t.x)
+            .collect();
+        assert_eq!(teeth.len(), 3, "{teeth:?}");
+        for (x, expected) in teeth.iter().zip([0.0, 1.35, 2.35]) {
+            assert!((x - expected).abs() < 1e-5, "{teeth:?}");
+        }
+    }
+
+    #[test]
+    fn phase_restarts_after_a_rest() {
+        let settings = CombSettings {
+            drag_speed: 1.0,
+            tooth_placement: ToothPlacement::PhaseContinuous,
+            ..CombSettings::default()
+        };
+        let segments = [segment(0.0, 0.5, 1.0, 60), segment(0.7, 2.0, 1.0, 62)];
+        let teeth: Vec<f32> = settings
+            .tooth_positions(&segments)
+            .iter()
+            .map(|t| t.x)
+            .collect();
+        assert_eq!(teeth, [0.0, 0.7, 1.7]);
+    }
+
+    #[test]
+    fn a_held_note_keeps_its_phase_when_the_top_note_ends() {
+        let settings = CombSettings {
+            tooth_placement: ToothPlacement::NoteStart,
+            chord_strategy: ChordStrategy::Highest,
+            ..CombSettings::default()
+        };
+        // C4 held from 0 s to 2 s under G4 from 0.5 s to 1.5 s
+        let segments = settings.get_comb_segments(&[note(60, 0.0, 2.0), note(67, 0.5, 1.5)]);
+        assert_eq!(segments.len(), 3);
+        let teeth = settings.tooth_positions(&segments);
+
+        // G4 strikes on a tooth; C4 comes back on the teeth it struck with at 0 s
+        let g4_start = 0.5 * settings.mm_per_unit();
+        assert!(teeth.iter().any(|t| (t.x - g4_start).abs() < 1e-4));
+        let resumed = 1.5 * settings.mm_per_unit();
+        let c4 = settings.calculate_spacing(60.0).unwrap();
+        let after: Vec<f32> = teeth
+            .iter()
+            .map(|t| t.x)
+            .filter(|&x| x >= resumed)
+            .collect();
+        assert!(!after.is_empty());
+        assert!(after[0] - resumed > 0.01 * c4, "re-phased at {resumed}");
+        for x in after {
+            let pitches = x / c4;
+            assert!(
+                (pitches - pitches.round()).abs() < 0.02,
+                "{x} is {pitches} C4 pitches in"
+            );
+        }
+    }
+
+    #[test]
+    fn notes_on_silent_keys_are_left_out_and_reported() {
+        let mut settings = CombSettings::default();
+        // Every other key from C4 sounds: C#4 is silent
+        settings.tuning.set_mapping(
+            "half",
+            KeyboardMapping::parse("2\n48\n72\n60\n60\n261.63\n1\n0\nx\n").unwrap(),
+        );
+        let notes = [note(60, 0.0, 2.0), note(61, 0.5, 1.5), note(61, 3.0, 4.0)];
+        assert_eq!(spans(&settings, &notes), [(0.0, 2.0, 60)]);
+        settings.multi_lane = true;
+        assert_eq!(settings.get_comb_lanes(&notes).len(), 1);
+        assert_eq!(
+            settings.silent_key_warning(&notes).as_deref(),
+            Some(
+                "2 notes fall on keys Equal temperament / half leaves silent and are left out (C#4)"
+            )
+        );
+        assert_eq!(settings.silent_key_warning(&notes[..1]), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Minimal ASCII DXF (R12) writer for laser and CNC software.
+//!
+//! Only what the comb exporters need: a layer table and LINE, POLYLINE and TEXT entities.
+//! R12 has no LWPOLYLINE, so outlines are written as classic POLYLINE/VERTEX/SEQEND runs,
+//! which every importer that reads R12 understands. Coordinates are in millimetres with
+//! the Y axis pointing up, as DXF expects.
+
+use std::fmt::Write;
+
+pub const LAYER_CUT: &str = "CUT";
+pub const LAYER_ENGRAVE: &str = "ENGRAVE";
+pub const LAYER_ANNOTATION: &str = "ANNOTATION";
+
+/// Layers written by the comb exporters, with their AutoCAD colour index.
+pub const COMB_LAYERS: [(&str, u8); 3] = [
+    (LAYER_CUT, 1),        // Red
+    (LAYER_ENGRAVE, 5),    // Blue
+    (LAYER_ANNOTATION, 3), // Green
+];
+
+pub struct DxfWriter {
+    layers: Vec<(String, u8)>,
+    entities: String,
+}
+
+impl DxfWriter {
+    pub fn new(layers: &[(&str, u8)]) -> Self {
+        Self {
+            layers: layers
+                .iter()
+                .map(|&(name, color)| (name.to_string(), color))
+                .collect(),
+            entities: String::new(),
+        }
+    }
+
+    fn pair(out: &mut String, code: u16, value: impl std::fmt::Display) {
+        let _ = write!(out, "{code}\n{value}\n");
+    }
+
+    fn point(out: &mut String, base: u16, (x, y): (f32, f32)) {
+        Self::pair(out, base, format!("{x:.4}"));
+        Self::pair(out, base + 10, format!("{y:.4}"));
+        Self::pair(out, base + 20, "0.0");
+    }
+
+    pub fn line(&mut self, layer: &str, from: (f32, f32), to: (f32, f32)) {
+        let out = &mut self.entities;
+        Self::pair(out, 0, "LINE");
+        Self::pair(out, 8, layer);
+        Self::point(out, 10, from);
+        Self::point(out, 11, to);
+    }
+
+    pub fn polyline(&mut self, layer: &str, points: &[(f32, f32)], closed: bool) {
+        if points.len() < 2 {
+            return;
+        }
+        let out = &mut self.entities;
+        Self::pair(out, 0, "POLYLINE");
+        Self::pair(out, 8, layer);
+        Self::pair(out, 66, 1); // Vertices follow
+        Self::point(out, 10, (0.0, 0.0));
+        Self::pair(out, 70, if closed { 1 } else { 0 });
+        for &p in points {
+            Self::pair(out, 0, "VERTEX");
+            Self::pair(out, 8, layer);
+            Self::point(out, 10, p);
+        }
+        Self::pair(out, 0, "SEQEND");
+        Self::pair(out, 8, layer);
+    }
+
+    pub fn text(&mut self, layer: &str, at: (f32, f32), height: f32, text: &str) {
+        let out = &mut self.entities;
+        Self::pair(out, 0, "TEXT");
+        Self::pair(out, 8, layer);
+        Self::point(out, 10, at);
+        Self::pair(out, 40, format!("{height:.3}"));
+        Self::pair(out, 1, text);
+    }
+
+    pub fn finish(self) -> String {
+        let mut out = String::new();
+
+        Self::pair(&mut out, 0, "SECTION");
+        Self::pair(&mut out, 2, "HEADER");
+        Self::pair(&mut out, 9, "$ACADVER");
+        Self::pair(&mut out, 1, "AC1009");
+        Self::pair(&mut out, 0, "ENDSEC");
+
+        Self::pair(&mut out, 0, "SECTION");
+        Self::pair(&mut out, 2, "TABLES");
+        // The layers refer to CONTINUOUS, which R12 readers expect to find defined here
+        Self::pair(&mut out, 0, "TABLE");
+        Self::pair(&mut out, 2, "LTYPE");
+        Self::pair(&mut out, 70, 1);
+        Self::pair(&mut out, 0, "LTYPE");
+        Self::pair(&mut out, 2, "CONTINUOUS");
+        Self::pair(&mut out, 70, 0);
+        Self::pair(&mut out, 3, "Solid line");
+        Self::pair(&mut out, 72, 65); // Alignment code, always 'A'
+        Self::pair(&mut out, 73, 0); // No dashes
+        Self::pair(&mut out, 40, "0.0");
+        Self::pair(&mut out, 0, "ENDTAB");
+        Self::pair(&mut out, 0, "TABLE");
+        Self::pair(&mut out, 2, "LAYER");
+        Self::pair(&mut out, 70, self.layers.len());
+        for (name, color) in &self.layers {
+            Self::pair(&mut out, 0, "LAYER");
+            Self::pair(&mut out, 2, name);
+            Self::pair(&mut out, 70, 0);
+            Self::pair(&mut out, 62, color);
+            Self::pair(&mut out, 6, "CONTINUOUS");
+        }
+        Self::pair(&mut out, 0, "ENDTAB");
+        Self::pair(&mut out, 0, "ENDSEC");
+
+        Self::pair(&mut out, 0, "SECTION");
+        Self::pair(&mut out, 2, "ENTITIES");
+        out.push_str(&self.entities);
+        Self::pair(&mut out, 0, "ENDSEC");
+        Self::pair(&mut out, 0, "EOF");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Group code and value pairs, in order.
+    fn pairs(dxf: &str) -> Vec<(u16, &str)> {
+        let lines: Vec<&str> = dxf.lines().collect();
+        assert_eq!(lines.len() % 2, 0, "odd number of lines");
+        lines
+            .chunks(2)
+            .map(|pair| (pair[0].trim().parse().unwrap(), pair[1]))
+            .collect()
+    }
+
+    /// Values of every pair with `code` between each `0 name` entity and the next.
+    fn entities<'a>(pairs: &[(u16, &'a str)], name: &str, code: u16) -> Vec<Vec<&'a str>> {
+        let mut found = Vec::new();
+        for (i, _) in pairs.iter().enumerate().filter(|(_, p)| **p == (0, name)) {
+            found.push(
+                pairs[i + 1..]
+                    .iter()
+                    .take_while(|p| p.0 != 0)
+                    .filter(|p| p.0 == code)
+                    .map(|p| p.1)
+                    .collect(),
+            );
+        }
+        found
+    }
+
+    fn drawing() -> String {
+        let mut dxf = DxfWriter::new(&COMB_LAYERS);
+        dxf.polyline(LAYER_CUT, &[(0.0, 0.0), (10.0, 0.0), (10.0, 5.0)], true);
+        dxf.polyline(LAYER_CUT, &[(20.0, 0.0), (30.0, 0.0)], false);
+        dxf.polyline(LAYER_CUT, &[(40.0, 0.0)], false); // Too short to write
+        dxf.line(LAYER_ENGRAVE, (1.0, 1.0), (1.0, 2.0));
+        dxf.text(LAYER_ANNOTATION, (1.2, 2.0), 1.5, "C#4");
+        dxf.finish()
+    }
+
+    #[test]
+    fn sections_come_in_r12_order() {
+        let dxf = drawing();
+        let pairs = pairs(&dxf);
+        let sections: Vec<&str> = pairs
+            .windows(2)
+            .filter(|w| w[0] == (0, "SECTION"))
+            .map(|w| w[1].1)
+            .collect();
+        assert_eq!(sections, ["HEADER", "TABLES", "ENTITIES"]);
+        assert_eq!(pairs.iter().filter(|p| **p == (0, "ENDSEC")).count(), 3);
+        assert_eq!(pairs.last(), Some(&(0, "EOF")));
+        assert_eq!(
+            pairs[..6],
+            [
+                (0, "SECTION"),
+                (2, "HEADER"),
+                (9, "$ACADVER"),
+                (1, "AC1009"),
+                (0, "ENDSEC"),
+                (0, "SECTION")
+            ]
+        );
+        assert!(!dxf.contains("$MEASUREMENT"));
+    }
+
+    #[test]
+    fn layers_use_a_defined_linetype() {
+        let dxf = drawing();
+        let pairs = pairs(&dxf);
+        let tables = entities(&pairs, "TABLE", 2);
+        assert_eq!(tables, [vec!["LTYPE"], vec!["LAYER"]]);
+        assert_eq!(entities(&pairs, "LTYPE", 2), [vec!["CONTINUOUS"]]);
+
+        let names = entities(&pairs, "LAYER", 2);
+        let colors = entities(&pairs, "LAYER", 62);
+        let linetypes = entities(&pairs, "LAYER", 6);
+        assert_eq!(names, [vec!["CUT"], vec!["ENGRAVE"], vec!["ANNOTATION"]]);
+        assert_eq!(colors, [vec!["1"], vec!["5"], vec!["3"]]);
+        assert!(linetypes.iter().all(|l| l == &["CONTINUOUS"]));
+        // The table's count covers its entries
+        let count = pairs
+            .windows(3)
+            .find(|w| w[0] == (0, "TABLE") && w[1] == (2, "LAYER"));
+        assert_eq!(count.map(|w| w[2]), Some((70, "3")));
+    }
+
+    #[test]
+    fn polylines_close_with_their_own_seqend() {
+        let dxf = drawing();
+        let pairs = pairs(&dxf);
+        let start = pairs.iter().position(|p| *p == (2, "ENTITIES")).unwrap();
+        let mut open = None;
+        let mut vertices = Vec::new();
+        for &(code, value) in &pairs[start..] {
+            if code != 0 {
+                continue;
+            }
+            match value {
+                "POLYLINE" => {
+                    assert_eq!(open, None, "POLYLINE inside another");
+                    open = Some(0);
+                }
+                "VERTEX" => *open.as_mut().expect("VERTEX outside a POLYLINE") += 1,
+                "SEQEND" => vertices.push(open.take().expect("SEQEND without a POLYLINE")),
+                _ => assert_eq!(open, None, "{value} inside a POLYLINE"),
+            }
+        }
+        assert_eq!(open, None);
+        assert_eq!(vertices, [3, 2]);
+        assert_eq!(entities(&pairs, "POLYLINE", 70), [vec!["1"], vec!["0"]]);
+        assert_eq!(entities(&pairs, "TEXT", 1), [vec!["C#4"]]);
+        assert_eq!(entities(&pairs, "LINE", 8), [vec!["ENGRAVE"]]);
+    }
+}
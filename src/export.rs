@@ -0,0 +1,427 @@
+//! Exporters: SVG and DXF outlines (one comb, or strips nested on sheets), G-code slot
+//! milling, PNG/TIFF bitmaps and tiled PDF printouts.
+
+use crate::comb::{CombLayout, CombSettings, LayoutMode};
+use crate::geometry::RestTreatment;
+use crate::midi::{MidiNote, midi_pitch_to_name};
+use crate::strip::StripError;
+use crate::{dxf, gcode, geometry, nest, pdf, raster};
+
+impl CombSettings {
+    pub fn generate_svg(&self, notes: &[MidiNote]) -> Result<String, StripError> {
+        const STROKE: f32 = 0.1;
+
+        let Some(layout) = self.comb_layout(notes) else {
+            let height = self.tooth_shape.row_height();
+            return Ok(format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="5mm" height="{height}mm" viewBox="0 0 5 {height}"></svg>"#
+            ));
+        };
+
+        let strips = self.comb_strips(&layout)?;
+        let mut svg_content = String::new();
+        for piece in &strips.pieces {
+            for outline in &piece.outlines {
+                svg_content.push_str(&format!(
+                    r#"<path d="{}" fill="none" stroke="black" stroke-width="{}" />"#,
+                    geometry::svg_path(&self.cut_outline(outline)),
+                    STROKE
+                ));
+            }
+            // Piece numbers in blue, which laser software usually takes as engraving
+            for ((x, y), text) in &piece.labels {
+                svg_content.push_str(&format!(
+                    r#"<text x="{x:.3}" y="{y:.3}" font-family="sans-serif" font-size="{:.3}" fill="blue">{text}</text>"#,
+                    self.label_height()
+                ));
+            }
+        }
+
+        Ok(format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.3}mm" height="{h:.3}mm" viewBox="0 0 {w:.3} {h:.3}">{}</svg>"#,
+            svg_content,
+            w = strips.width,
+            h = strips.height,
+        ))
+    }
+
+    /// Every sheet of a nesting in one SVG, stacked top to bottom. Sheet edges are drawn in
+    /// light grey as a guide; parts are cut in black and labels engraved in blue.
+    pub fn generate_nested_svg(&self, nesting: &nest::Nesting) -> String {
+        const STROKE: f32 = 0.1;
+
+        let mut svg_content = String::new();
+        for sheet in 0..nesting.sheets {
+            let (x, y) = nesting.sheet_origin(sheet);
+            svg_content.push_str(&format!(
+                r#"<rect x="{x:.3}" y="{y:.3}" width="{:.3}" height="{:.3}" fill="none" stroke="silver" stroke-width="{STROKE}" />"#,
+                nesting.sheet_width, nesting.sheet_height
+            ));
+        }
+        for part in &nesting.parts {
+            let (_, sheet_y) = nesting.sheet_origin(part.sheet);
+            let place = |(x, y): geometry::Point| (x, y + sheet_y);
+            let outline: Vec<geometry::Point> = self
+                .cut_outline(&part.outline)
+                .into_iter()
+                .map(place)
+                .collect();
+            svg_content.push_str(&format!(
+                r#"<path d="{}" fill="none" stroke="black" stroke-width="{}" />"#,
+                geometry::svg_path(&outline),
+                STROKE
+            ));
+            for &(at, ref text) in &part.labels {
+                let (x, y) = place(at);
+                svg_content.push_str(&format!(
+                    r#"<text x="{x:.3}" y="{y:.3}" font-family="sans-serif" font-size="{:.3}" fill="blue">{text}</text>"#,
+                    self.label_height()
+                ));
+            }
+        }
+
+        let (width, height) = nesting.export_size();
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.3}mm" height="{h:.3}mm" viewBox="0 0 {w:.3} {h:.3}">{}</svg>"#,
+            svg_content,
+            w = width,
+            h = height,
+        )
+    }
+
+    /// DXF version of a nesting: parts on the cut layer with their note changes and labels
+    /// engraved, and sheet edges and note names as annotation.
+    pub fn generate_nested_dxf(&self, nesting: &nest::Nesting) -> String {
+        let mut dxf = dxf::DxfWriter::new(&dxf::COMB_LAYERS);
+        let (_, height) = nesting.export_size();
+        let text_height = self.label_height();
+        for sheet in 0..nesting.sheets {
+            let (x, y) = nesting.sheet_origin(sheet);
+            let (right, bottom) = (x + nesting.sheet_width, y + nesting.sheet_height);
+            let corners = [(x, y), (right, y), (right, bottom), (x, bottom)];
+            let corners: Vec<geometry::Point> =
+                corners.iter().map(|&(x, y)| (x, height - y)).collect();
+            dxf.polyline(dxf::LAYER_ANNOTATION, &corners, true);
+        }
+        for part in &nesting.parts {
+            // DXF's Y axis points up, so flip every sheet around the export height
+            let (_, sheet_y) = nesting.sheet_origin(part.sheet);
+            let flip = |(x, y): geometry::Point| (x, height - (y + sheet_y));
+            let outline: Vec<geometry::Point> = self
+                .cut_outline(&part.outline)
+                .into_iter()
+                .map(flip)
+                .collect();
+            dxf.polyline(dxf::LAYER_CUT, &outline, true);
+            for &(at, pitch) in &part.notes {
+                let (x, base) = flip(at);
+                dxf.line(dxf::LAYER_ENGRAVE, (x, base), (x, base - text_height));
+                dxf.text(
+                    dxf::LAYER_ANNOTATION,
+                    (x + 0.2, base - text_height),
+                    text_height * 0.8,
+                    &midi_pitch_to_name(pitch),
+                );
+            }
+            for &(at, ref text) in &part.labels {
+                dxf.text(dxf::LAYER_ENGRAVE, flip(at), text_height, text);
+            }
+        }
+        dxf.finish()
+    }
+
+    /// The comb filled black on white at `raster_settings.dpi`, at true scale.
+    pub fn generate_raster(
+        &self,
+        notes: &[MidiNote],
+        format: raster::RasterFormat,
+    ) -> Result<Vec<u8>, raster::RasterError> {
+        let Some(layout) = self.comb_layout(notes) else {
+            return Err(raster::RasterError::NothingToDraw);
+        };
+        let strips = self.comb_strips(&layout)?;
+        let outlines: Vec<Vec<geometry::Point>> = strips
+            .pieces
+            .iter()
+            .flat_map(|piece| &piece.outlines)
+            .map(|outline| self.cut_outline(outline))
+            .collect();
+        raster::render(
+            &outlines,
+            (strips.width, strips.height),
+            self.kerf_offset(),
+            format,
+            &self.raster_settings,
+        )
+    }
+
+    /// Printable PDF of the comb at true scale, tiled over pages of `pdf_settings.paper`,
+    /// behind a cover page naming `song` and `tracks` and listing the calibration.
+    pub fn generate_pdf(
+        &self,
+        notes: &[MidiNote],
+        song: &str,
+        tracks: &[String],
+    ) -> Result<Vec<u8>, pdf::PdfError> {
+        let Some(layout) = self.comb_layout(notes) else {
+            return Err(pdf::PdfError::NothingToDraw);
+        };
+        let strips = self.comb_strips(&layout)?;
+        let outlines: Vec<Vec<geometry::Point>> = strips
+            .pieces
+            .iter()
+            .flat_map(|piece| &piece.outlines)
+            .map(|outline| self.cut_outline(outline))
+            .collect();
+
+        let mut timing = self.layout_mode.label().to_string();
+        if self.layout_mode == LayoutMode::Beats {
+            timing.push_str(&format!(" at {:.0} BPM", self.playing_bpm));
+        }
+        let voices = if self.multi_lane {
+            format!(
+                "{} lanes, {}",
+                layout.lanes.len(),
+                self.voice_drop_rule.label()
+            )
+        } else {
+            self.chord_strategy.label().to_string()
+        };
+        let shape = &self.tooth_shape;
+        let teeth = format!(
+            "{}, {:.1} mm deep on a {:.1} mm spine, {:.0}% of the pitch wide",
+            shape.profile.label(),
+            shape.depth,
+            shape.spine,
+            shape.duty_cycle * 100.0
+        );
+        let mut details = vec![
+            ("Tracks", tracks.join(", ")),
+            ("Drag speed", format!("{:.1} mm/s", self.drag_speed)),
+            ("Timing", timing),
+            ("Concert pitch", format!("{:.2} Hz", self.concert_pitch)),
+            ("Tuning", self.tuning.name.clone()),
+            ("Voices", voices),
+            ("Teeth", teeth),
+            ("Tooth placement", self.tooth_placement.label().to_string()),
+            ("Rests", self.rest_treatment.label().to_string()),
+            ("Repeated notes", self.articulation.label().to_string()),
+        ];
+        if strips.pieces.len() > 1 {
+            details.push((
+                "Strips",
+                format!(
+                    "{} pieces of at most {:.0} mm, {} joints",
+                    strips.pieces.len(),
+                    self.max_strip_length,
+                    self.strip_joint.label().to_lowercase()
+                ),
+            ));
+        }
+        if self.kerf > 0.0 {
+            details.push((
+                "Kerf",
+                format!("{:.2} mm, {}", self.kerf, self.kerf_direction.label()),
+            ));
+        }
+        let cover = pdf::Cover {
+            title: song.to_string(),
+            details: details
+                .into_iter()
+                .map(|(label, value)| (label.to_string(), value))
+                .collect(),
+        };
+        pdf::render(
+            &outlines,
+            (strips.width, strips.height),
+            self.kerf_offset(),
+            &cover,
+            &self.pdf_settings,
+        )
+    }
+
+    /// Milling program for the comb: every stretch of spine between teeth is cleared as a
+    /// slot from the tip edge. Non-square profiles are milled as square teeth. Only one-row
+    /// combs in one piece can be milled: every other row's tips face stock shared with the
+    /// row above, and the joints between strips are not cut.
+    pub fn generate_gcode(&self, notes: &[MidiNote]) -> Result<String, gcode::GcodeError> {
+        let Some(layout) = self.comb_layout(notes) else {
+            return Err(gcode::GcodeError::NothingToCut);
+        };
+        let slots = self.milling_slots(&layout)?;
+        gcode::generate(&slots, &self.gcode_settings)
+    }
+
+    /// Every slot [`generate_gcode`](Self::generate_gcode) mills for `layout`, in machine
+    /// coordinates.
+    fn milling_slots(&self, layout: &CombLayout) -> Result<Vec<gcode::Slot>, gcode::GcodeError> {
+        let [lane] = layout.lanes.as_slice() else {
+            return Err(gcode::GcodeError::MultipleRows {
+                rows: layout.lanes.len(),
+            });
+        };
+        let breaks = self.strip_breaks(layout)?;
+        if !breaks.is_empty() {
+            return Err(gcode::GcodeError::SplitComb {
+                pieces: breaks.len() + 1,
+            });
+        }
+
+        // Machine Y points up, with the origin at the bottom-left corner of the layout
+        let flip = |y: f32| layout.height - y;
+        let mut slots = Vec::new();
+        let gaps = geometry::gap_spans(
+            &lane.teeth,
+            &lane.rests,
+            self.rest_treatment,
+            layout.width,
+            &self.tooth_shape,
+        );
+        for (left, right) in gaps {
+            let center_x = (left + right) / 2.0;
+            let label = lane
+                .segments
+                .iter()
+                .find(|&&(start, end, _)| center_x >= start && center_x < end)
+                .map_or_else(|| "rest".to_string(), |&(_, _, p)| midi_pitch_to_name(p));
+            slots.push(gcode::Slot {
+                center_x,
+                width: right - left,
+                open_y: flip(lane.top),
+                end_y: flip(lane.base),
+                label,
+            });
+        }
+        if self.rest_treatment == RestTreatment::Notches {
+            // Milled after the rest is cleared; a notch narrower than the tool is simply cut
+            // at tool width
+            for &rest in &lane.rests {
+                let (left, right, depth) = geometry::notch_span(rest, &self.tooth_shape);
+                slots.push(gcode::Slot {
+                    center_x: (left + right) / 2.0,
+                    width: (right - left).max(self.gcode_settings.tool_diameter),
+                    open_y: flip(lane.top),
+                    end_y: flip(lane.base + depth),
+                    label: "notch".to_string(),
+                });
+            }
+        }
+        Ok(slots)
+    }
+
+    /// DXF version of the comb: the outline on the cut layer, note changes engraved on the
+    /// spine, and note names as annotation.
+    pub fn generate_dxf(&self, notes: &[MidiNote]) -> Result<String, StripError> {
+        let mut dxf = dxf::DxfWriter::new(&dxf::COMB_LAYERS);
+        let Some(layout) = self.comb_layout(notes) else {
+            return Ok(dxf.finish());
+        };
+
+        // DXF's Y axis points up, so flip the pieces around the sheet height
+        let strips = self.comb_strips(&layout)?;
+        let flip = |(x, y): geometry::Point| (x, strips.height - y);
+        let text_height = self.label_height();
+        for piece in &strips.pieces {
+            for (lane, outline) in layout.lanes.iter().zip(&piece.outlines) {
+                let outline: Vec<geometry::Point> =
+                    self.cut_outline(outline).into_iter().map(flip).collect();
+                dxf.polyline(dxf::LAYER_CUT, &outline, true);
+
+                let segments = lane
+                    .segments
+                    .iter()
+                    .filter(|s| s.0 >= piece.start && s.0 < piece.end);
+                for &(start, _, pitch) in segments {
+                    let (x, base) = flip(piece.place((start, lane.base)));
+                    dxf.line(dxf::LAYER_ENGRAVE, (x, base), (x, base - text_height));
+                    dxf.text(
+                        dxf::LAYER_ANNOTATION,
+                        (x + 0.2, base - text_height),
+                        text_height * 0.8,
+                        &midi_pitch_to_name(pitch),
+                    );
+                }
+            }
+            for &(at, ref text) in &piece.labels {
+                dxf.text(dxf::LAYER_ENGRAVE, flip(at), text_height, text);
+            }
+        }
+        Ok(dxf.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comb::LaneLayout;
+    use crate::geometry::Tooth;
+
+    /// One row 4 mm long with teeth 0.5 mm wide at 1.5 and 2.5 mm, leaving 0.5 mm between
+    /// them and open spine at both ends.
+    fn two_teeth() -> CombLayout {
+        CombLayout {
+            x_offset: 0.0,
+            width: 4.0,
+            height: 12.0,
+            lanes: vec![LaneLayout {
+                top: 0.0,
+                base: 8.0,
+                bottom: 12.0,
+                teeth: vec![
+                    Tooth {
+                        x: 1.5,
+                        spacing: 1.0,
+                    },
+                    Tooth {
+                        x: 2.5,
+                        spacing: 1.0,
+                    },
+                ],
+                outline: Vec::new(),
+                segments: vec![(0.0, 4.0, 69)],
+                rests: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn every_gap_between_and_beside_the_teeth_is_a_slot() {
+        let slots = CombSettings::default().milling_slots(&two_teeth()).unwrap();
+        let spans: Vec<(f32, f32)> = slots.iter().map(|s| (s.center_x, s.width)).collect();
+        assert_eq!(spans, [(0.625, 1.25), (2.0, 0.5), (3.375, 1.25)]);
+        // Cut from the tips, at the top of the layout, down to the spine
+        assert!(slots.iter().all(|s| s.open_y == 12.0 && s.end_y == 4.0));
+        assert_eq!(slots[1].label, "A4");
+    }
+
+    #[test]
+    fn a_tool_wider_than_the_gap_between_teeth_is_refused() {
+        let mut settings = CombSettings::default();
+        settings.gcode_settings.tool_diameter = 0.6;
+        let slots = settings.milling_slots(&two_teeth()).unwrap();
+        let Err(gcode::GcodeError::SlotTooNarrow {
+            center_x, width, ..
+        }) = gcode::generate(&slots, &settings.gcode_settings)
+        else {
+            panic!("a 0.6 mm tool was given a 0.5 mm slot");
+        };
+        assert_eq!((center_x, width), (2.0, 0.5));
+    }
+
+    #[test]
+    fn a_split_comb_is_refused() {
+        let settings = CombSettings {
+            max_strip_length: 3.0,
+            strip_joint: geometry::StripJoint::Dovetail,
+            tooth_shape: geometry::ToothShape {
+                spine: 1.0,
+                ..CombSettings::default().tooth_shape
+            },
+            ..CombSettings::default()
+        };
+        assert!(matches!(
+            settings.milling_slots(&two_teeth()),
+            Err(gcode::GcodeError::SplitComb { pieces: 2 })
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+//! G-code toolpaths for milling comb slots with a flat end mill.
+//!
+//! Every slot is cut from the open edge of the comb inwards. The tool centre is kept one
+//! radius inside the slot walls, so the finished slot has the width that was asked for
+//! rather than being widened by the cutter. Output is metric, absolute, XY plane.
+
+use crate::strip::StripError;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GcodeSettings {
+    pub tool_diameter: f32, // mm
+    pub feed_rate: f32,     // mm/min
+    pub plunge_rate: f32,   // mm/min
+    pub cut_depth: f32,     // Total depth below the stock surface, mm
+    pub passes: u32,        // Number of equal step-downs to reach `cut_depth`
+    pub safe_z: f32,        // Travel height above the stock, mm
+    pub spindle_rpm: u32,
+}
+
+impl Default for GcodeSettings {
+    fn default() -> Self {
+        Self {
+            tool_diameter: 0.5,
+            feed_rate: 300.0,
+            plunge_rate: 60.0,
+            cut_depth: 1.5,
+            passes: 3,
+            safe_z: 5.0,
+            spindle_rpm: 12000,
+        }
+    }
+}
+
+/// A slot to mill, in machine coordinates (mm).
+pub struct Slot {
+    pub center_x: f32,
+    pub width: f32,
+    /// Y of the open edge of the stock. The cut starts outside it.
+    pub open_y: f32,
+    /// Y of the closed end of the slot.
+    pub end_y: f32,
+    /// Shown in error messages to locate the offending slot.
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GcodeError {
+    NothingToCut,
+    InvalidToolDiameter,
+    /// Only the first row's teeth open onto a free edge; the others face the stock between
+    /// rows, which slots cut from the tip edge would plunge into.
+    MultipleRows {
+        rows: usize,
+    },
+    /// Slots are milled into one continuous blank; the connectors between strips are not.
+    SplitComb {
+        pieces: usize,
+    },
+    SlotTooNarrow {
+        center_x: f32,
+        width: f32,
+        tool_diameter: f32,
+        label: String,
+    },
+    Strips(StripError),
+}
+
+impl fmt::Display for GcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GcodeError::NothingToCut => {
+                write!(f, "Nothing to cut: no notes in the selected track.")
+            }
+            GcodeError::InvalidToolDiameter => {
+                write!(f, "Tool diameter must be greater than zero.")
+            }
+            GcodeError::MultipleRows { rows } => write!(
+                f,
+                "G-code can only mill a one-row comb, and this one has {rows} rows. Limit the \
+                 comb to one row, or cut it from the SVG or DXF export."
+            ),
+            GcodeError::SplitComb { pieces } => write!(
+                f,
+                "G-code can only mill a comb in one piece, and this one is split into {pieces} \
+                 strips. Turn splitting off, or cut the strips from the SVG or DXF export."
+            ),
+            GcodeError::SlotTooNarrow {
+                center_x,
+                width,
+                tool_diameter,
+                label,
+            } => write!(
+                f,
+                "Slot at x = {center_x:.2} mm ({label}) is {width:.3} mm wide, narrower than the \
+                 {tool_diameter:.3} mm tool. Use a smaller tool or a slower drag speed."
+            ),
+            GcodeError::Strips(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for GcodeError {}
+
+impl From<StripError> for GcodeError {
+    fn from(e: StripError) -> Self {
+        GcodeError::Strips(e)
+    }
+}
+
+/// How far outside the stock each cut starts, so the tool never plunges into material.
+const LEAD_IN: f32 = 1.0;
+
+pub fn generate(slots: &[Slot], settings: &GcodeSettings) -> Result<String, GcodeError> {
+    let d = settings.tool_diameter;
+    if d <= 0.0 {
+        return Err(GcodeError::InvalidToolDiameter);
+    }
+    if let Some(slot) = slots.iter().find(|s| s.width + 1e-4 < d) {
+        return Err(GcodeError::SlotTooNarrow {
+            center_x: slot.center_x,
+            width: slot.width,
+            tool_diameter: d,
+            label: slot.label.clone(),
+        });
+    }
+
+    let r = d / 2.0;
+    let passes = settings.passes.max(1);
+    let step_down = settings.cut_depth / passes as f32;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "(Musical comb, {} slots, {:.3} mm end mill)",
+        slots.len(),
+        d
+    );
+    let _ = writeln!(out, "G21 G90 G17");
+    let _ = writeln!(out, "G0 Z{:.3}", settings.safe_z);
+    let _ = writeln!(out, "M3 S{}", settings.spindle_rpm);
+
+    for slot in slots {
+        // The cut runs away from the open edge, whichever side of the slot that is
+        let direction = (slot.end_y - slot.open_y).signum();
+        let start_y = slot.open_y - direction * (r + LEAD_IN);
+        let stop_y = slot.end_y - direction * r;
+
+        // Tool-centre lines: one per wall, plus enough in between to clear the middle
+        let left = slot.center_x - slot.width / 2.0 + r;
+        let right = slot.center_x + slot.width / 2.0 - r;
+        let lines = ((right - left) / (0.8 * d)).ceil().max(0.0) as usize + 1;
+        let xs: Vec<f32> = (0..lines)
+            .map(|i| {
+                if lines == 1 {
+                    slot.center_x
+                } else {
+                    left + (right - left) * i as f32 / (lines - 1) as f32
+                }
+            })
+            .collect();
+
+        let _ = writeln!(out, "({})", slot.label);
+        for pass in 1..=passes {
+            let z = -(step_down * pass as f32);
+            let _ = writeln!(out, "G0 X{:.4} Y{:.4}", xs[0], start_y);
+            let _ = writeln!(out, "G1 Z{:.4} F{:.1}", z, settings.plunge_rate);
+            // Zig-zag across the slot so the tool stays down for the whole pass
+            for (i, &x) in xs.iter().enumerate() {
+                let (from, to) = if i % 2 == 0 {
+                    (start_y, stop_y)
+                } else {
+                    (stop_y, start_y)
+                };
+                if i > 0 {
+                    let _ = writeln!(out, "G1 X{:.4} Y{:.4} F{:.1}", x, from, settings.feed_rate);
+                }
+                let _ = writeln!(out, "G1 Y{:.4} F{:.1}", to, settings.feed_rate);
+            }
+            let _ = writeln!(out, "G0 Z{:.3}", settings.safe_z);
+        }
+    }
+
+    let _ = writeln!(out, "M5");
+    let _ = writeln!(out, "M30");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2 mm slot centred on x = 5, open at y = 10 and closed at y = 2.
+    fn slot(width: f32) -> Slot {
+        Slot {
+            center_x: 5.0,
+            width,
+            open_y: 10.0,
+            end_y: 2.0,
+            label: "A4".to_string(),
+        }
+    }
+
+    /// Every value of `axis` in the program's moves, in order.
+    fn words(program: &str, axis: char) -> Vec<f32> {
+        program
+            .lines()
+            .filter(|line| line.starts_with('G'))
+            .flat_map(|line| line.split_whitespace())
+            .filter_map(|word| word.strip_prefix(axis))
+            .map(|value| value.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn slots_are_cleared_inside_their_walls_in_equal_step_downs() {
+        // 0.5 mm tool, 1.5 mm deep in three passes
+        let program = generate(&[slot(2.0)], &GcodeSettings::default()).unwrap();
+        assert!(program.starts_with("(Musical comb, 1 slots, 0.500 mm end mill)"));
+
+        let depths: Vec<f32> = words(&program, 'Z')
+            .into_iter()
+            .filter(|&z| z < 0.0)
+            .collect();
+        assert_eq!(depths, [-0.5, -1.0, -1.5]);
+
+        // Tool centre one radius inside each wall
+        let xs = words(&program, 'X');
+        let (min, max) = xs
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &x| (lo.min(x), hi.max(x)));
+        assert_eq!((min, max), (4.25, 5.75));
+
+        // Led in from a millimetre past the open edge, stopping a radius short of the end
+        let ys = words(&program, 'Y');
+        assert_eq!(ys[0], 11.25);
+        let (min, max) = ys
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &y| (lo.min(y), hi.max(y)));
+        assert_eq!((min, max), (2.25, 11.25));
+    }
+
+    #[test]
+    fn a_slot_as_wide_as_the_tool_is_one_straight_cut() {
+        let settings = GcodeSettings {
+            passes: 1,
+            ..GcodeSettings::default()
+        };
+        let program = generate(&[slot(0.5)], &settings).unwrap();
+        assert!(words(&program, 'X').iter().all(|&x| x == 5.0));
+        assert_eq!(program.matches("G1 Y").count(), 1);
+    }
+
+    #[test]
+    fn a_slot_narrower_than_the_tool_is_refused() {
+        assert_eq!(
+            generate(&[slot(0.4)], &GcodeSettings::default()),
+            Err(GcodeError::SlotTooNarrow {
+                center_x: 5.0,
+                width: 0.4,
+                tool_diameter: 0.5,
+                label: "A4".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,562 @@
+//! Cuttable comb geometry: turns tooth positions into one closed outline per comb row.
+//!
+//! Coordinates are in mm with Y pointing down, like SVG. Teeth point up, towards the top
+//! of the row, and hang off a solid spine along the bottom.
+
+use serde::{Deserialize, Serialize};
+
+pub type Point = (f32, f32);
+
+/// A tooth position in mm, with the pitch of the teeth around it.
+#[derive(Clone, Copy, Debug)]
+pub struct Tooth {
+    pub x: f32,
+    pub spacing: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ToothProfile {
+    Square,
+    Triangular,
+    Sawtooth,
+    Rounded,
+}
+
+impl ToothProfile {
+    pub const ALL: [ToothProfile; 4] = [
+        ToothProfile::Square,
+        ToothProfile::Triangular,
+        ToothProfile::Sawtooth,
+        ToothProfile::Rounded,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ToothProfile::Square => "Square",
+            ToothProfile::Triangular => "Triangular",
+            ToothProfile::Sawtooth => "Sawtooth",
+            ToothProfile::Rounded => "Rounded",
+        }
+    }
+}
+
+/// What a comb row carries where no note sounds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RestTreatment {
+    /// Bare spine, which the stylus keeps rubbing against.
+    PlainSpine,
+    /// A smooth bar up to the tooth tips that lifts the stylus clear of the spine.
+    DamperBar,
+    /// Bare spine with a narrow notch in the middle of each rest, to line the comb up by.
+    Notches,
+}
+
+impl RestTreatment {
+    pub const ALL: [RestTreatment; 3] = [
+        RestTreatment::PlainSpine,
+        RestTreatment::DamperBar,
+        RestTreatment::Notches,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RestTreatment::PlainSpine => "Plain spine",
+            RestTreatment::DamperBar => "Raised damper bar",
+            RestTreatment::Notches => "Registration notches",
+        }
+    }
+}
+
+/// Connector cut into the spine where a comb is split into pieces.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum StripJoint {
+    /// A flared tab on the earlier piece that slides into a matching socket.
+    Dovetail,
+    /// A round knob on a narrow neck, like a jigsaw puzzle piece.
+    Pin,
+}
+
+impl StripJoint {
+    pub const ALL: [StripJoint; 2] = [StripJoint::Dovetail, StripJoint::Pin];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StripJoint::Dovetail => "Dovetail",
+            StripJoint::Pin => "Round pin",
+        }
+    }
+
+    /// How far the tab reaches past the cut on a spine this tall, in mm.
+    pub fn length(self, spine: f32) -> f32 {
+        match self {
+            StripJoint::Dovetail => spine * 0.6,
+            StripJoint::Pin => spine * 0.8,
+        }
+    }
+
+    /// The cut across a spine from `base` down to `bottom` at `x`, with the tab pointing
+    /// towards +x. The piece on the left follows it as its end, the piece on the right
+    /// follows it backwards as its start, so tab and socket match exactly.
+    pub fn edge(self, x: f32, base: f32, bottom: f32) -> Vec<Point> {
+        let spine = bottom - base;
+        let middle = (base + bottom) / 2.0;
+        let length = self.length(spine);
+        let mut points = vec![(x, base)];
+        match self {
+            StripJoint::Dovetail => {
+                let (neck, head) = (0.2 * spine, 0.35 * spine);
+                points.extend([
+                    (x, middle - neck),
+                    (x + length, middle - head),
+                    (x + length, middle + head),
+                    (x, middle + neck),
+                ]);
+            }
+            StripJoint::Pin => {
+                let (neck, radius) = (0.15 * spine, 0.3 * spine);
+                let center = x + length - radius;
+                // Round the head from where it meets the top of the neck to the bottom
+                let meet = (neck / radius).asin();
+                let sweep = 2.0 * (std::f32::consts::PI - meet);
+                points.push((x, middle - neck));
+                for step in 0..=ARC_STEPS * 4 {
+                    let angle =
+                        meet - std::f32::consts::PI + sweep * step as f32 / (ARC_STEPS * 4) as f32;
+                    points.push((center + radius * angle.cos(), middle + radius * angle.sin()));
+                }
+                points.push((x, middle + neck));
+            }
+        }
+        points.push((x, bottom));
+        points
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ToothShape {
+    pub profile: ToothProfile,
+    /// Tooth width as a fraction of the tooth pitch, measured at the base.
+    pub duty_cycle: f32,
+    pub depth: f32,
+    pub spine: f32,
+}
+
+impl ToothShape {
+    pub fn row_height(&self) -> f32 {
+        self.depth + self.spine
+    }
+
+    /// Width of a tooth at its base for a given pitch.
+    pub fn tooth_width(&self, spacing: f32) -> f32 {
+        spacing * self.duty_cycle.clamp(0.05, 0.95)
+    }
+}
+
+/// Segments used to approximate a quarter circle on rounded tips.
+const ARC_STEPS: usize = 6;
+
+/// Width of a registration notch, in mm.
+const NOTCH_WIDTH: f32 = 1.0;
+
+/// Registration notch in the rest `left..right`: its left and right edge, and how far it
+/// reaches into the spine.
+pub fn notch_span((left, right): (f32, f32), shape: &ToothShape) -> (f32, f32, f32) {
+    let center = (left + right) / 2.0;
+    let half = NOTCH_WIDTH.min(right - left) / 2.0;
+    (center - half, center + half, shape.spine / 2.0)
+}
+
+/// Rests (toothless stretches, in drag order) trimmed clear of any tooth reaching into them.
+pub fn clear_rests(
+    teeth: &[Tooth],
+    rests: &[(f32, f32)],
+    width: f32,
+    shape: &ToothShape,
+) -> Vec<(f32, f32)> {
+    let spans = tooth_spans(teeth, width, shape);
+    rests
+        .iter()
+        .filter_map(|&(left, right)| {
+            let at = spans.partition_point(|&(l, _)| l < left);
+            let left = spans
+                .get(at.wrapping_sub(1))
+                .map_or(left, |t| left.max(t.1));
+            let right = spans.get(at).map_or(right, |t| right.min(t.0));
+            (right - left > 1e-4).then_some((left, right))
+        })
+        .collect()
+}
+
+/// Left and right edge of every tooth at its base, in drag order.
+///
+/// Teeth are centred on their positions. A tooth that would overlap its neighbour is
+/// trimmed, and one trimmed away entirely is dropped, so the spans never overlap.
+pub fn tooth_spans(teeth: &[Tooth], width: f32, shape: &ToothShape) -> Vec<(f32, f32)> {
+    let mut spans = Vec::with_capacity(teeth.len());
+    let mut last_right = 0.0f32;
+    for tooth in teeth {
+        let half = shape.tooth_width(tooth.spacing) / 2.0;
+        let left = (tooth.x - half).max(last_right);
+        let right = (tooth.x + half).min(width);
+        if right - left < 1e-4 {
+            continue;
+        }
+        spans.push((left, right));
+        last_right = right;
+    }
+    spans
+}
+
+/// Stretches of spine between teeth and damper bars (and at both ends) that the cutter has
+/// to clear. `rests` must be clear of the teeth, see [`clear_rests`].
+pub fn gap_spans(
+    teeth: &[Tooth],
+    rests: &[(f32, f32)],
+    treatment: RestTreatment,
+    width: f32,
+    shape: &ToothShape,
+) -> Vec<(f32, f32)> {
+    let mut raised = tooth_spans(teeth, width, shape);
+    if treatment == RestTreatment::DamperBar {
+        raised.extend_from_slice(rests);
+    }
+    raised.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut gaps = Vec::new();
+    let mut last_right = 0.0f32;
+    for (left, right) in raised {
+        if left > last_right {
+            gaps.push((last_right, left));
+        }
+        last_right = right;
+    }
+    if width > last_right {
+        gaps.push((last_right, width));
+    }
+    gaps
+}
+
+/// Closed outline of one comb row spanning `0..width`, with its tips at `top` and `rests`
+/// (clear of the teeth, see [`clear_rests`]) cut as `treatment` asks.
+pub fn comb_outline(
+    teeth: &[Tooth],
+    rests: &[(f32, f32)],
+    treatment: RestTreatment,
+    width: f32,
+    top: f32,
+    shape: &ToothShape,
+) -> Vec<Point> {
+    let base = top + shape.depth;
+    let bottom = base + shape.spine;
+
+    enum Feature {
+        Tooth,
+        Bar,
+        Notch(f32),
+    }
+    let mut features: Vec<(f32, f32, Feature)> = tooth_spans(teeth, width, shape)
+        .into_iter()
+        .map(|(left, right)| (left, right, Feature::Tooth))
+        .collect();
+    features.extend(rests.iter().filter_map(|&rest| match treatment {
+        RestTreatment::PlainSpine => None,
+        RestTreatment::DamperBar => Some((rest.0, rest.1, Feature::Bar)),
+        RestTreatment::Notches => {
+            let (left, right, depth) = notch_span(rest, shape);
+            Some((left, right, Feature::Notch(depth)))
+        }
+    }));
+    features.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut points = vec![(0.0, bottom), (0.0, base)];
+    for (left, right, feature) in features {
+        let center = (left + right) / 2.0;
+        let half = (right - left) / 2.0;
+
+        let profile = match feature {
+            Feature::Tooth => shape.profile,
+            Feature::Bar => ToothProfile::Square,
+            Feature::Notch(depth) => {
+                points.extend([
+                    (left, base),
+                    (left, base + depth),
+                    (right, base + depth),
+                    (right, base),
+                ]);
+                continue;
+            }
+        };
+        match profile {
+            ToothProfile::Square => {
+                points.extend([(left, base), (left, top), (right, top), (right, base)]);
+            }
+            ToothProfile::Triangular => {
+                points.extend([(left, base), (center, top), (right, base)]);
+            }
+            ToothProfile::Sawtooth => {
+                // Ramp up in the drag direction, drop straight back to the spine
+                points.extend([(left, base), (right, top), (right, base)]);
+            }
+            ToothProfile::Rounded => {
+                let radius = half.min(shape.depth);
+                let arc_y = top + radius;
+                points.push((left, base));
+                for step in 0..=ARC_STEPS * 2 {
+                    let angle = std::f32::consts::PI * (1.0 - step as f32 / (ARC_STEPS * 2) as f32);
+                    points.push((center + half * angle.cos(), arc_y - radius * angle.sin()));
+                }
+                points.push((right, base));
+            }
+        }
+    }
+
+    points.extend([(width, base), (width, bottom)]);
+    points
+}
+
+/// The part of a comb row outline (as made by [`comb_outline`]) between `left` and `right`,
+/// closed with the joint edges given for either end or with a straight cut.
+///
+/// Each edge runs from the tooth side of the spine to the back, see [`StripJoint::edge`].
+pub fn piece_outline(
+    outline: &[Point],
+    (left, right): (f32, f32),
+    left_edge: Option<&[Point]>,
+    right_edge: Option<&[Point]>,
+) -> Vec<Point> {
+    let (bottom, base) = (outline[0].1, outline[1].1);
+    // Between the two end caps the outline only moves right or straight up and down
+    let contour = &outline[1..outline.len() - 1];
+    let height_at = |x: f32| {
+        let at = contour
+            .windows(2)
+            .position(|w| w[0].0 <= x && x <= w[1].0 && w[1].0 > w[0].0)
+            .unwrap_or(0);
+        let (a, b) = (contour[at], contour[at + 1]);
+        a.1 + (b.1 - a.1) * ((x - a.0) / (b.0 - a.0)).clamp(0.0, 1.0)
+    };
+
+    let mut points = match left_edge {
+        Some(edge) => edge.iter().rev().copied().collect(),
+        None => vec![(left, bottom), (left, base)],
+    };
+    points.push((left, height_at(left)));
+    points.extend(contour.iter().filter(|p| p.0 > left && p.0 < right));
+    points.push((right, height_at(right)));
+    match right_edge {
+        Some(edge) => points.extend_from_slice(edge),
+        None => points.extend([(right, base), (right, bottom)]),
+    }
+    points
+}
+
+/// SVG path data for a closed outline.
+pub fn svg_path(points: &[Point]) -> String {
+    let mut d = String::with_capacity(points.len() * 16);
+    for (i, (x, y)) in points.iter().enumerate() {
+        d.push_str(if i == 0 { "M" } else { " L" });
+        d.push_str(&format!("{x:.3} {y:.3}"));
+    }
+    d.push_str(" Z");
+    d
+}
+
+/// Offsets a closed outline by `distance` mm: outwards when positive, inwards when negative.
+///
+/// Corners are mitred, except sharp ones that would spike far past the original point
+/// (triangle tips, say), which are bevelled instead.
+pub fn offset_outline(points: &[Point], distance: f32) -> Vec<Point> {
+    const MITER_LIMIT: f32 = 3.0;
+
+    let mut pts: Vec<Point> = Vec::with_capacity(points.len());
+    for &p in points {
+        if pts
+            .last()
+            .is_none_or(|&q| (p.0 - q.0).hypot(p.1 - q.1) > 1e-5)
+        {
+            pts.push(p);
+        }
+    }
+    while pts.len() > 1 {
+        let (first, last) = (pts[0], pts[pts.len() - 1]);
+        if (first.0 - last.0).hypot(first.1 - last.1) > 1e-5 {
+            break;
+        }
+        pts.pop();
+    }
+    if distance == 0.0 || pts.len() < 3 {
+        return pts;
+    }
+
+    let n = pts.len();
+    let area: f32 = (0..n)
+        .map(|i| {
+            let (a, b) = (pts[i], pts[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum();
+    // Outward normal is to the right of travel for a positive-area (counter-clockwise) ring
+    let sign = if area > 0.0 { 1.0 } else { -1.0 };
+    let normals: Vec<Point> = (0..n)
+        .map(|i| {
+            let (a, b) = (pts[i], pts[(i + 1) % n]);
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let len = dx.hypot(dy);
+            (sign * dy / len, -sign * dx / len)
+        })
+        .collect();
+
+    let mut out = Vec::with_capacity(n + 8);
+    for i in 0..n {
+        let p = pts[i];
+        let a = normals[(i + n - 1) % n];
+        let b = normals[i];
+        let dot = a.0 * b.0 + a.1 * b.1;
+        // Convex corners spike when offset outwards, concave ones when offset inwards
+        let cross = a.0 * b.1 - a.1 * b.0;
+        let spikes = (cross * sign > 0.0) == (distance > 0.0);
+        let ratio_sq = 2.0 / (1.0 + dot).max(1e-6);
+
+        if spikes && ratio_sq > MITER_LIMIT * MITER_LIMIT {
+            out.push((p.0 + a.0 * distance, p.1 + a.1 * distance));
+            out.push((p.0 + b.0 * distance, p.1 + b.1 * distance));
+        } else {
+            let scale = distance / (1.0 + dot).max(1e-6);
+            out.push((p.0 + (a.0 + b.0) * scale, p.1 + (a.1 + b.1) * scale));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: ToothShape = ToothShape {
+        profile: ToothProfile::Square,
+        duty_cycle: 0.5,
+        depth: 2.0,
+        spine: 3.0,
+    };
+
+    fn assert_near(actual: &[Point], expected: &[Point]) {
+        assert_eq!(actual.len(), expected.len(), "{actual:?}");
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.0 - e.0).abs() < 1e-5 && (a.1 - e.1).abs() < 1e-5,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rests_take_a_damper_bar_or_a_notch() {
+        // A row with no teeth and a rest from 4 to 8; base at 2, back edge at 5
+        let outline = |treatment| comb_outline(&[], &[(4.0, 8.0)], treatment, 12.0, 0.0, &SQUARE);
+        let ends = [(0.0, 5.0), (0.0, 2.0)];
+        let back = [(12.0, 2.0), (12.0, 5.0)];
+        assert_eq!(outline(RestTreatment::PlainSpine), [ends, back].concat());
+        assert_eq!(
+            outline(RestTreatment::DamperBar),
+            [
+                &ends[..],
+                &[(4.0, 2.0), (4.0, 0.0), (8.0, 0.0), (8.0, 2.0)],
+                &back[..]
+            ]
+            .concat()
+        );
+        // 1 mm wide, half the spine deep, centred in the rest
+        assert_eq!(
+            outline(RestTreatment::Notches),
+            [
+                &ends[..],
+                &[(5.5, 2.0), (5.5, 3.5), (6.5, 3.5), (6.5, 2.0)],
+                &back[..]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn square_tooth_outline() {
+        let teeth = [Tooth {
+            x: 2.0,
+            spacing: 2.0,
+        }];
+        let outline = comb_outline(&teeth, &[], RestTreatment::PlainSpine, 4.0, 0.0, &SQUARE);
+        assert_eq!(
+            outline,
+            [
+                (0.0, 5.0),
+                (0.0, 2.0),
+                (1.5, 2.0),
+                (1.5, 0.0),
+                (2.5, 0.0),
+                (2.5, 2.0),
+                (4.0, 2.0),
+                (4.0, 5.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn piece_cut_through_a_tooth_starts_at_its_tip() {
+        let teeth = [Tooth {
+            x: 2.0,
+            spacing: 2.0,
+        }];
+        let outline = comb_outline(&teeth, &[], RestTreatment::PlainSpine, 4.0, 0.0, &SQUARE);
+        assert_eq!(
+            piece_outline(&outline, (2.0, 4.0), None, None),
+            [
+                (2.0, 5.0),
+                (2.0, 2.0),
+                (2.0, 0.0),
+                (2.5, 0.0),
+                (2.5, 2.0),
+                (4.0, 2.0),
+                (4.0, 2.0),
+                (4.0, 5.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn rectangle_offsets_outward_and_inward() {
+        let rectangle = [(0.0, 0.0), (4.0, 0.0), (4.0, 2.0), (0.0, 2.0)];
+        assert_eq!(
+            offset_outline(&rectangle, 0.5),
+            [(-0.5, -0.5), (4.5, -0.5), (4.5, 2.5), (-0.5, 2.5)]
+        );
+        assert_eq!(
+            offset_outline(&rectangle, -0.5),
+            [(0.5, 0.5), (3.5, 0.5), (3.5, 1.5), (0.5, 1.5)]
+        );
+        // The repeated closing point is ignored
+        let closed = [rectangle.as_slice(), &[(0.0, 0.0)]].concat();
+        assert_eq!(offset_outline(&closed, 0.5).len(), 4);
+    }
+
+    #[test]
+    fn sharp_tip_is_bevelled_outward_and_mitred_inward() {
+        // A tall, thin triangle with its tip at (1, 0)
+        let triangle = [(0.0, 10.0), (1.0, 0.0), (2.0, 10.0)];
+        let edge = 101.0f32.sqrt();
+
+        // Mitring the tip outwards would put it ten units up, so it is cut off square
+        let outward = offset_outline(&triangle, 1.0);
+        assert_near(
+            &outward[1..3],
+            &[
+                (1.0 - 10.0 / edge, -1.0 / edge),
+                (1.0 + 10.0 / edge, -1.0 / edge),
+            ],
+        );
+        assert_eq!(outward.len(), 4);
+
+        // Inwards the tip only moves down the middle of the triangle
+        let inward = offset_outline(&triangle, -0.1);
+        assert_eq!(inward.len(), 3);
+        assert_near(&inward[1..2], &[(1.0, edge / 10.0)]);
+    }
+}
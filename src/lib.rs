@@ -0,0 +1,43 @@
+//! Musical comb generation: MIDI parsing, tooth spacing physics, comb geometry and
+//! exporters for laser cutters and CNC mills.
+//!
+//! ```no_run
+//! use midi_to_bar::{CombSettings, LoadOptions, load_midi};
+//!
+//! let song = load_midi("song.mid".as_ref(), &LoadOptions::default())?;
+//! let svg = CombSettings::default().generate_svg(&song.tracks[0].notes)?;
+//! std::fs::write("comb.svg", svg)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod cli;
+pub mod comb;
+pub mod dxf;
+mod export;
+pub mod gcode;
+pub mod geometry;
+pub mod gm;
+pub mod midi;
+pub mod nest;
+pub mod pdf;
+pub mod project;
+pub mod raster;
+pub mod strip;
+pub mod tuning;
+
+pub use comb::{
+    Articulation, ChordStrategy, CombLayout, CombSegment, CombSettings, DurationUnit,
+    KerfDirection, LaneLayout, LayoutMode, ToothPlacement, VoiceDropRule, pack_lanes,
+};
+pub use gcode::{GcodeError, GcodeSettings};
+pub use geometry::{RestTreatment, StripJoint, ToothProfile, ToothShape};
+pub use midi::{
+    LoadError, LoadOptions, MidiNote, Song, TrackData, load_midi, merge_notes, midi_pitch_to_name,
+    parse_midi,
+};
+pub use nest::{NestError, NestSettings, NestedPart, Nesting};
+pub use pdf::{PaperSize, PdfError, PdfSettings};
+pub use project::{NoteEdit, NoteSource, Project, ProjectError, apply_edits, set_edit};
+pub use raster::{RasterError, RasterFormat, RasterSettings};
+pub use strip::{CombStrips, StripError, StripPiece};
+pub use tuning::{KeyboardMapping, Scale, Tuning, TuningError, TuningPreset};
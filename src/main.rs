@@ -1,31 +1,95 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use eframe::egui;
-use midly::{MetaMessage, Smf, TrackEventKind};
+use midi_to_bar::geometry;
+use midi_to_bar::{
+    Articulation, ChordStrategy, CombLayout, CombSettings, DurationUnit, KerfDirection, LayoutMode,
+    LoadOptions, MidiNote, NoteEdit, NoteSource, PaperSize, Project, RasterFormat, RestTreatment,
+    Song, StripJoint, ToothPlacement, ToothProfile, TrackData, Tuning, TuningPreset, VoiceDropRule,
+    apply_edits, merge_notes, midi_pitch_to_name, pack_lanes, set_edit,
+};
 use rfd::FileDialog;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-struct MidiNote {
-    pitch: u8,
-    start_time: f32,
-    duration: f32,
+/// Unit the drag speed is displayed and edited in. Internally it is always mm/s.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SpeedUnit {
+    MillimetersPerSecond,
+    CentimetersPerSecond,
 }
 
-struct TrackData {
-    name: String,
-    notes: Vec<MidiNote>,
+impl SpeedUnit {
+    fn label(self) -> &'static str {
+        match self {
+            SpeedUnit::MillimetersPerSecond => "mm/s",
+            SpeedUnit::CentimetersPerSecond => "cm/s",
+        }
+    }
+
+    fn mm_per_unit(self) -> f32 {
+        match self {
+            SpeedUnit::MillimetersPerSecond => 1.0,
+            SpeedUnit::CentimetersPerSecond => 10.0,
+        }
+    }
+}
+
+/// A comb layout kept until the settings or notes it was made from change. Laying out a
+/// long song takes longer than a frame, and the sidebar and preview need it every frame.
+#[derive(Default)]
+struct LayoutCache {
+    key: Option<(CombSettings, Vec<MidiNote>)>,
+    layout: Option<Rc<CombLayout>>,
+    kerf_warning: Option<Option<String>>, // Worked out on first use
+}
+
+impl LayoutCache {
+    fn layout(&mut self, settings: &CombSettings, notes: &[MidiNote]) -> Option<Rc<CombLayout>> {
+        let fresh = self
+            .key
+            .as_ref()
+            .is_some_and(|(s, n)| s == settings && n.as_slice() == notes);
+        if !fresh {
+            self.layout = settings.comb_layout(notes).map(Rc::new);
+            self.kerf_warning = None;
+            self.key = Some((settings.clone(), notes.to_vec()));
+        }
+        self.layout.clone()
+    }
+
+    fn kerf_warning(&mut self, settings: &CombSettings, notes: &[MidiNote]) -> Option<String> {
+        let layout = self.layout(settings, notes)?;
+        self.kerf_warning
+            .get_or_insert_with(|| settings.kerf_warning(&layout))
+            .clone()
+    }
 }
 
 struct MidiVisualizer {
     tracks: Option<Vec<TrackData>>,
-    selected_track: usize,
-    ref_note: i32,
-    ref_spacing: f32, // Spacing in pixels for the reference note
-    px_per_beat: f32, // How many pixels one musical beat occupies
+    selected_track: usize,       // Track shown in the note editor
+    selected_tracks: Vec<usize>, // Tracks merged into the comb, always including the above
+    source: Option<NoteSource>,  // Where the tracks came from, for saving projects
+    edits: Vec<NoteEdit>,
+    project_path: Option<PathBuf>,
+    embed_notes: bool,
+    load_options: LoadOptions,
+    settings: CombSettings,
+    speed_unit: SpeedUnit,
+    px_per_mm: f32, // Preview zoom
+    initial_bpm: f32,
+    has_tempo_changes: bool,
     file_path: String,
     export_status: String,
+    load_error: Option<String>, // Shown until dismissed
+    load_warnings: Vec<String>,
     scroll_offset: f32, // Horizontal scroll position
     scroll_to: Option<f32>,
+    compare_placement: Option<ToothPlacement>, // Second comb drawn in the preview
+    layout_cache: LayoutCache,
+    compare_cache: LayoutCache, // Layout for `compare_placement`
 }
 
 impl Default for MidiVisualizer {
@@ -33,338 +97,1024 @@ impl Default for MidiVisualizer {
         Self {
             tracks: None,
             selected_track: 0,
-            ref_note: 60,       // C4
-            ref_spacing: 10.0,  // Base spacing for C4
-            px_per_beat: 200.0, // Length of one beat
+            selected_tracks: vec![0],
+            source: None,
+            edits: Vec::new(),
+            project_path: None,
+            embed_notes: false,
+            load_options: LoadOptions::default(),
+            settings: CombSettings::default(),
+            speed_unit: SpeedUnit::MillimetersPerSecond,
+            px_per_mm: 10.0,
+            initial_bpm: 120.0,
+            has_tempo_changes: false,
             file_path: "No file loaded".to_string(),
             export_status: String::new(),
+            load_error: None,
+            load_warnings: Vec::new(),
             scroll_offset: 0.0,
             scroll_to: None,
+            compare_placement: None,
+            layout_cache: LayoutCache::default(),
+            compare_cache: LayoutCache::default(),
         }
     }
 }
 
-struct CombSegment {
-    start_time: f32,
-    end_time: f32,
-    spacing: f32,
-}
-
 impl MidiVisualizer {
-    fn load_midi(&mut self, path: std::path::PathBuf) {
-        let Ok(data) = fs::read(&path) else { return };
-        let Ok(smf) = Smf::parse(&data) else { return };
-
-        let ticks_per_beat = match smf.header.timing {
-            midly::Timing::Metrical(t) => t.as_int() as f32,
-            _ => 480.0,
+    fn load_midi(&mut self, path: PathBuf) {
+        let song = match midi_to_bar::load_midi(&path, &self.load_options) {
+            Ok(song) => song,
+            Err(e) => {
+                self.load_error = Some(format!("Could not load {}: {e}", path.display()));
+                return;
+            }
         };
+        self.settings.playing_bpm = song.initial_bpm;
+        self.set_song(song);
+        self.file_path = path.to_string_lossy().into_owned();
+        self.source = Some(NoteSource::Midi { path });
+        self.edits.clear();
+        self.project_path = None;
+    }
 
-        let mut parsed_tracks = Vec::new();
-        for (i, track) in smf.tracks.into_iter().enumerate() {
-            let mut notes = Vec::new();
-            let mut current_ticks = 0u32;
-            let mut active_notes = std::collections::HashMap::new();
-            let mut track_name = format!("Track {}", i);
-
-            for event in track {
-                current_ticks += event.delta.as_int();
-                match event.kind {
-                    TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
-                        if let Ok(s) = std::str::from_utf8(name) {
-                            track_name = s.to_string();
-                        }
-                    }
-                    TrackEventKind::Midi { message, .. } => match message {
-                        midly::MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
-                            active_notes.insert(key.as_int(), current_ticks);
-                        }
-                        midly::MidiMessage::NoteOn { key, .. }
-                        | midly::MidiMessage::NoteOff { key, .. } => {
-                            if let Some(start) = active_notes.remove(&key.as_int()) {
-                                notes.push(MidiNote {
-                                    pitch: key.as_int(),
-                                    start_time: start as f32 / ticks_per_beat,
-                                    duration: (current_ticks - start) as f32 / ticks_per_beat,
-                                });
-                            }
-                        }
-                        _ => {}
-                    },
-                    _ => {}
-                }
-            }
-            if !notes.is_empty() {
-                notes.sort_by(|a, b| a.start_time.partial_cmp(&b.start_time).unwrap());
-                parsed_tracks.push(TrackData {
-                    name: track_name,
-                    notes,
-                });
+    /// Reads the MIDI source again after the load options changed. Track and note numbers
+    /// may shift, so edits made against the old tracks are dropped.
+    fn reload(&mut self) {
+        let Some(NoteSource::Midi { path }) = &self.source else {
+            return;
+        };
+        match midi_to_bar::load_midi(path, &self.load_options) {
+            Ok(song) => {
+                self.set_song(song);
+                self.edits.clear();
             }
+            Err(e) => self.load_error = Some(format!("Could not load {}: {e}", path.display())),
         }
-        self.tracks = Some(parsed_tracks);
-        self.file_path = path.to_string_lossy().into_owned();
+    }
+
+    fn set_song(&mut self, song: Song) {
+        self.load_error = None;
+        self.load_warnings = song.warnings;
+        self.tracks = Some(song.tracks);
+        self.initial_bpm = song.initial_bpm;
+        self.has_tempo_changes = song.has_tempo_changes;
         self.selected_track = 0;
+        self.selected_tracks = vec![0];
         self.scroll_offset = 0.0;
         self.scroll_to = None;
+        let notes = self.selected_notes();
+        self.load_warnings
+            .extend(self.settings.silent_key_warning(&notes));
     }
 
-    fn calculate_spacing(&self, pitch: f32) -> f32 {
-        // f = 440 * 2^((n-69)/12)
-        let ref_freq = 440.0 * 2.0f32.powf((self.ref_note as f32 - 69.0) / 12.0);
-        let note_freq = 440.0 * 2.0f32.powf((pitch - 69.0) / 12.0);
-        // Spacing is wavelength: S = S_ref * (F_ref / F_note)
-        self.ref_spacing * (ref_freq / note_freq)
+    fn open_project(&mut self, path: PathBuf) {
+        let opened =
+            Project::load(&path).and_then(|project| Ok((project.source_song(&path)?, project)));
+        let (song, project) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.load_error = Some(format!("Could not open {}: {e}", path.display()));
+                return;
+            }
+        };
+        self.set_song(song);
+        self.selected_track = project.selected_track;
+        self.selected_tracks = project.comb_tracks();
+        self.embed_notes = matches!(project.source, NoteSource::Embedded { .. });
+        self.load_options = project.load_options;
+        // Keep the MIDI path absolute, so saving elsewhere still finds it
+        let source = match project.midi_path(&path) {
+            Some(midi) => {
+                self.file_path = midi.to_string_lossy().into_owned();
+                NoteSource::Midi { path: midi }
+            }
+            None => {
+                self.file_path = format!("{} (embedded notes)", path.display());
+                project.source
+            }
+        };
+        self.source = Some(source);
+        self.settings = project.settings;
+        self.edits = project.edits;
+        if let Some(zoom) = project.preview_px_per_mm {
+            self.px_per_mm = zoom;
+        }
+        self.export_status = format!("Opened {}", path.display());
+        self.project_path = Some(path);
     }
 
-    fn generate_svg(&self) -> String {
-        let mut svg_content = String::new();
+    fn save_project(&mut self, path: PathBuf) {
+        let (Some(tracks), Some(source)) = (&self.tracks, &self.source) else {
+            return;
+        };
+        let source = if self.embed_notes {
+            NoteSource::Embedded {
+                song: Song {
+                    tracks: tracks.clone(),
+                    initial_bpm: self.initial_bpm,
+                    has_tempo_changes: self.has_tempo_changes,
+                    warnings: Vec::new(),
+                },
+            }
+        } else {
+            source.clone()
+        };
+        let mut project = Project::new(source, self.settings.clone());
+        project.load_options = self.load_options;
+        project.selected_track = self.selected_track;
+        project.selected_tracks = self.selected_tracks.clone();
+        project.edits = self.edits.clone();
+        project.preview_px_per_mm = Some(self.px_per_mm);
+        match project.save(&path) {
+            Ok(()) => {
+                self.export_status = format!("Saved {}", path.display());
+                self.project_path = Some(path);
+            }
+            Err(e) => self.export_status = format!("Could not save project: {e}"),
+        }
+    }
 
-        let segments = self.get_comb_segments();
-        if segments.is_empty() {
-            return format!(
-                r#"<svg xmlns="http://www.w3.org/2000/svg" width="50" height="100"></svg>"#
-            );
+    fn save_project_as(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("comb project", &["json"])
+            .set_file_name("comb_project.json")
+            .save_file()
+        {
+            self.save_project(path);
         }
+    }
 
-        let x_offset = segments.first().unwrap().start_time * self.px_per_beat;
-        let mut max_x: f32 = 0.0;
-
-        for segment in &segments {
-            let start_x = segment.start_time * self.px_per_beat;
-            let end_x = segment.end_time * self.px_per_beat;
-            let spacing = segment.spacing;
-
-            if spacing > 0.1 {
-                let first_tooth_index = (start_x / spacing).ceil() as i64;
-                let mut current_x_abs = first_tooth_index as f32 * spacing;
-
-                while current_x_abs < end_x {
-                    let current_x_relative = current_x_abs - x_offset;
-                    // Use a small epsilon to avoid floating point issues at the start
-                    if current_x_relative >= -f32::EPSILON {
-                        svg_content.push_str(&format!(
-                            r#"<line x1="{:.2}" y1="0" x2="{:.2}" y2="100" stroke="black" stroke-width="0.5" />"#,
-                            current_x_relative, current_x_relative
-                        ));
-                    }
-                    current_x_abs += spacing;
-                }
+    /// Writes an exported file and reports how it went in the status line. Returns whether
+    /// the file was written.
+    fn write_export(&mut self, path: &Path, content: impl AsRef<[u8]>, what: &str) -> bool {
+        match fs::write(path, content) {
+            Ok(()) => {
+                self.export_status = format!("{what} Exported successfully.");
+                true
+            }
+            Err(e) => {
+                self.export_status = format!("Could not write {}: {e}", path.display());
+                false
             }
-            max_x = max_x.max(end_x);
         }
-
-        let total_width = max_x - x_offset;
-
-        format!(
-            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.2}" height="100">{}</svg>"#,
-            total_width + 50.0,
-            svg_content
-        )
     }
 
-    fn get_comb_segments(&self) -> Vec<CombSegment> {
+    /// Notes of each selected track with edits applied, keyed by track index.
+    fn selected_parts(&self) -> Vec<(usize, Vec<MidiNote>)> {
         let Some(tracks) = &self.tracks else {
-            return vec![];
-        };
-        let Some(track_data) = tracks.get(self.selected_track) else {
-            return vec![];
+            return Vec::new();
         };
+        self.selected_tracks
+            .iter()
+            .filter_map(|&i| {
+                let track = tracks.get(i)?;
+                Some((i, apply_edits(&track.notes, i, &self.edits)))
+            })
+            .collect()
+    }
 
-        if track_data.notes.is_empty() {
-            return vec![];
-        }
+    /// The merged notes the comb is made from, or nothing when no file is loaded.
+    fn selected_notes(&self) -> Vec<MidiNote> {
+        merge_notes(
+            self.selected_parts()
+                .iter()
+                .map(|(_, notes)| notes.as_slice()),
+        )
+    }
 
-        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-        enum EventType {
-            On,
-            Off,
-        }
-        struct Event {
-            time: f32,
-            kind: EventType,
-            pitch: u8,
-        }
-        let mut events = Vec::new();
-        for note in &track_data.notes {
-            events.push(Event {
-                time: note.start_time,
-                kind: EventType::On,
-                pitch: note.pitch,
-            });
-            events.push(Event {
-                time: note.start_time + note.duration,
-                kind: EventType::Off,
-                pitch: note.pitch,
-            });
-        }
-        events.sort_by(|a, b| {
-            a.time
-                .partial_cmp(&b.time)
-                .unwrap_or(std::cmp::Ordering::Equal)
-                .then_with(|| a.kind.cmp(&b.kind))
-        });
+    /// Preview pixels per layout unit.
+    fn px_per_unit(&self) -> f32 {
+        self.settings.mm_per_unit() * self.px_per_mm
+    }
 
-        let mut segments = Vec::new();
-        let mut active_pitches = std::collections::BTreeSet::new();
-        let mut last_time = if events.is_empty() {
-            0.0
+    /// Draws a millimetre ruler along the top of the preview, measured from the song start.
+    fn draw_ruler(&self, painter: &egui::Painter, rect: egui::Rect, clip: egui::Rect) {
+        // Pick the smallest labelled step that leaves room for the text
+        let major_mm = [1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0]
+            .into_iter()
+            .find(|step| step * self.px_per_mm >= 60.0)
+            .unwrap_or(5000.0);
+        let minor_mm = if major_mm / 10.0 * self.px_per_mm >= 5.0 {
+            major_mm / 10.0
         } else {
-            events[0].time
+            major_mm / 2.0
         };
 
-        for event in &events {
-            let current_time = event.time;
-            if current_time > last_time && !active_pitches.is_empty() {
-                let sum_of_pitches: f32 = active_pitches.iter().map(|&p| p as f32).sum();
-                let average_pitch = sum_of_pitches / active_pitches.len() as f32;
-                segments.push(CombSegment {
-                    start_time: last_time,
-                    end_time: current_time,
-                    spacing: self.calculate_spacing(average_pitch),
-                });
-            }
-
-            match event.kind {
-                EventType::On => {
-                    active_pitches.insert(event.pitch);
-                }
-                EventType::Off => {
-                    active_pitches.remove(&event.pitch);
-                }
-            }
-            last_time = current_time;
-        }
-
-        // Merge segments
-        if segments.is_empty() {
-            return vec![];
-        }
+        let top = rect.min.y;
+        painter.rect_filled(
+            egui::Rect::from_min_max(rect.min, egui::pos2(rect.max.x, top + 24.0)),
+            0.0,
+            egui::Color32::from_rgb(35, 35, 42),
+        );
 
-        let mut merged = Vec::new();
-        let mut iter = segments.into_iter();
-        let mut current = iter.next().unwrap();
-
-        for next in iter {
-            // Using an epsilon for f32 comparison
-            if (next.spacing - current.spacing).abs() < f32::EPSILON
-                && (next.start_time - current.end_time).abs() < f32::EPSILON
-            {
-                current.end_time = next.end_time;
-            } else {
-                merged.push(current);
-                current = next;
+        let first = ((clip.left() - rect.min.x) / self.px_per_mm / minor_mm)
+            .floor()
+            .max(0.0) as i64;
+        let last = ((clip.right() - rect.min.x) / self.px_per_mm / minor_mm).ceil() as i64;
+        let minors_per_major = (major_mm / minor_mm).round() as i64;
+        for i in first..=last {
+            let mm = i as f32 * minor_mm;
+            let x = rect.min.x + mm * self.px_per_mm;
+            let is_major = i % minors_per_major == 0;
+            let tick = if is_major { 10.0 } else { 5.0 };
+            painter.line_segment(
+                [egui::pos2(x, top + 24.0 - tick), egui::pos2(x, top + 24.0)],
+                egui::Stroke::new(1.0, egui::Color32::from_gray(150)),
+            );
+            if is_major {
+                painter.text(
+                    egui::pos2(x + 2.0, top + 2.0),
+                    egui::Align2::LEFT_TOP,
+                    format!("{} mm", mm),
+                    egui::FontId::proportional(10.0),
+                    egui::Color32::from_gray(180),
+                );
             }
         }
-        merged.push(current);
-
-        merged
-    }
-
-    fn midi_pitch_to_name(&self, pitch: u8) -> String {
-        const NOTE_NAMES: [&str; 12] = [
-            "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
-        ];
-        let octave = (pitch as i16 / 12) - 1;
-        let note_index = (pitch % 12) as usize;
-        format!("{}{}", NOTE_NAMES[note_index], octave)
     }
 }
 
 impl eframe::App for MidiVisualizer {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::SidePanel::left("sidebar").show(ctx, |ui| {
-            ui.heading("Musical Comb Designer");
+            // Every section together is far taller than the window
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.heading("Musical Comb Designer");
 
-            if ui.button("📂 Load MIDI").clicked() {
-                if let Some(path) = FileDialog::new()
-                    .add_filter("midi", &["mid", "midi"])
-                    .pick_file()
+                if ui.button("📂 Load MIDI").clicked()
+                    && let Some(path) = FileDialog::new()
+                        .add_filter("midi", &["mid", "midi"])
+                        .pick_file()
                 {
                     self.load_midi(path);
                 }
-            }
+                ui.horizontal(|ui| {
+                    if ui.button("Open Project").clicked()
+                        && let Some(path) = FileDialog::new()
+                            .add_filter("comb project", &["json"])
+                            .pick_file()
+                    {
+                        self.open_project(path);
+                    }
+                    let can_save = self.tracks.is_some();
+                    if ui
+                        .add_enabled(can_save, egui::Button::new("💾 Save"))
+                        .clicked()
+                    {
+                        match self.project_path.clone() {
+                            Some(path) => self.save_project(path),
+                            None => self.save_project_as(),
+                        }
+                    }
+                    if ui
+                        .add_enabled(can_save, egui::Button::new("Save As…"))
+                        .clicked()
+                    {
+                        self.save_project_as();
+                    }
+                });
+                ui.checkbox(&mut self.embed_notes, "Embed notes in project");
+                // Embedded notes were read with these options when saved and cannot be read again
+                let splittable = !matches!(self.source, Some(NoteSource::Embedded { .. }));
+                ui.add_enabled_ui(splittable, |ui| {
+                    let options = &mut self.load_options;
+                    let mut changed = ui
+                        .checkbox(&mut options.split_channels, "Split multi-channel tracks")
+                        .changed();
+                    if options.split_channels {
+                        changed |= ui
+                            .checkbox(
+                                &mut options.include_percussion,
+                                "Keep channel 10 percussion",
+                            )
+                            .changed();
+                    }
+                    changed |= ui
+                    .checkbox(&mut options.sustain_pedal, "Hold notes under the pedals")
+                    .on_hover_text(
+                        "End notes when the sustain or sostenuto pedal lifts, not at key release",
+                    )
+                    .changed();
+                    if changed {
+                        self.reload();
+                    }
+                });
 
-            ui.label(format!("File: {}", self.file_path));
-            ui.separator();
-
-            if let Some(tracks) = &self.tracks {
-                ui.label("Select Track:");
-                egui::ScrollArea::vertical()
-                    .max_height(200.0)
-                    .show(ui, |ui| {
-                        for i in 0..tracks.len() {
-                            let track = &tracks[i];
-                            if ui
-                                .selectable_label(
-                                    self.selected_track == i,
-                                    format!("{}: {} ({} notes)", i, track.name, track.notes.len()),
-                                )
-                                .clicked()
-                            {
-                                self.selected_track = i;
+                ui.label(format!("File: {}", self.file_path));
+                if let Some(error) = &self.load_error
+                    && notice_panel(
+                        ui,
+                        egui::Color32::from_rgb(230, 90, 90),
+                        std::slice::from_ref(error),
+                    )
+                {
+                    self.load_error = None;
+                }
+                if !self.load_warnings.is_empty()
+                    && notice_panel(
+                        ui,
+                        egui::Color32::from_rgb(230, 190, 80),
+                        &self.load_warnings,
+                    )
+                {
+                    self.load_warnings.clear();
+                }
+                ui.separator();
+
+                if let Some(tracks) = &self.tracks {
+                    ui.label("Select Track (Ctrl-click to merge several):");
+                    egui::ScrollArea::vertical()
+                        .max_height(200.0)
+                        .show(ui, |ui| {
+                            let merging = self.selected_tracks.len() > 1;
+                            for (i, track) in tracks.iter().enumerate() {
+                                let selected = self.selected_tracks.contains(&i);
+                                let mut text = egui::RichText::new(format!(
+                                    "{}: {} ({} notes)",
+                                    i,
+                                    track.name,
+                                    track.notes.len()
+                                ));
+                                if merging && selected {
+                                    text = text.color(track_color(i));
+                                }
+                                if i == self.selected_track {
+                                    text = text.strong();
+                                }
+                                if !ui.selectable_label(selected, text).clicked() {
+                                    continue;
+                                }
+                                if !ui.input(|input| input.modifiers.command) {
+                                    self.selected_tracks = vec![i];
+                                    self.selected_track = i;
+                                } else if !selected {
+                                    self.selected_tracks.push(i);
+                                    self.selected_tracks.sort_unstable();
+                                    self.selected_track = i;
+                                } else if merging {
+                                    self.selected_tracks.retain(|&t| t != i);
+                                    if self.selected_track == i {
+                                        self.selected_track = self.selected_tracks[0];
+                                    }
+                                }
+                            }
+                        });
+
+                    let track = self.selected_track;
+                    if let Some(notes) = tracks.get(track).map(|t| &t.notes) {
+                        let edits = &mut self.edits;
+                        let count = edits.iter().filter(|e| e.track == track).count();
+                        egui::CollapsingHeader::new(format!("Note Edits ({count})")).show(
+                            ui,
+                            |ui| {
+                                if ui.button("Clear edits").clicked() {
+                                    edits.retain(|e| e.track != track);
+                                }
+                                egui::ScrollArea::vertical().max_height(200.0).show_rows(
+                                    ui,
+                                    18.0,
+                                    notes.len(),
+                                    |ui, rows| {
+                                        for i in rows {
+                                            let original = &notes[i];
+                                            let edit = edits
+                                                .iter()
+                                                .find(|e| (e.track, e.note) == (track, i));
+                                            let mut pitch = edit
+                                                .and_then(|e| e.pitch)
+                                                .unwrap_or(original.pitch);
+                                            let mut muted = edit.is_some_and(|e| e.muted);
+                                            ui.horizontal(|ui| {
+                                                ui.label(format!(
+                                                    "{i}: {:.2} s",
+                                                    original.start_seconds
+                                                ));
+                                                let mut changed = ui
+                                                    .add(
+                                                        egui::DragValue::new(&mut pitch)
+                                                            .range(0..=127),
+                                                    )
+                                                    .changed();
+                                                ui.label(midi_pitch_to_name(pitch));
+                                                changed |=
+                                                    ui.checkbox(&mut muted, "Mute").changed();
+                                                if original.soft {
+                                                    ui.weak("soft").on_hover_text(
+                                                        "Played with the soft pedal down",
+                                                    );
+                                                }
+                                                if changed {
+                                                    set_edit(
+                                                        edits,
+                                                        NoteEdit {
+                                                            track,
+                                                            note: i,
+                                                            pitch: (pitch != original.pitch)
+                                                                .then_some(pitch),
+                                                            muted,
+                                                        },
+                                                    );
+                                                }
+                                            });
+                                        }
+                                    },
+                                );
+                            },
+                        );
+                    }
+                }
+
+                ui.separator();
+                ui.label("Physics Calibration");
+                let reference = midi_pitch_to_name(self.settings.tuning.mapping.reference_key);
+                ui.add(
+                    egui::Slider::new(&mut self.settings.concert_pitch, 400.0..=480.0)
+                        // A keyboard mapping may put the reference on a key far from A4
+                        .clamping(egui::SliderClamping::Never)
+                        .text(format!("Reference Pitch {reference} (Hz)")),
+                );
+                egui::ComboBox::from_label("Tuning")
+                    .selected_text(&self.settings.tuning.name)
+                    .show_ui(ui, |ui| {
+                        for preset in TuningPreset::ALL {
+                            let current = self.settings.tuning.name == preset.label();
+                            if ui.selectable_label(current, preset.label()).clicked() {
+                                if self.settings.tuning.mapping.reference_key != 69 {
+                                    self.settings.concert_pitch = 440.0;
+                                }
+                                self.settings.tuning = preset.tuning();
                             }
                         }
                     });
-            }
+                ui.horizontal(|ui| {
+                    if ui.button("Load .scl").clicked()
+                        && let Some(path) = FileDialog::new()
+                            .add_filter("Scala scale", &["scl"])
+                            .pick_file()
+                    {
+                        match Tuning::load_scl(&path) {
+                            Ok(scale) => self.settings.tuning.set_scale(&file_stem(&path), scale),
+                            Err(e) => {
+                                self.load_error =
+                                    Some(format!("Could not load {}: {e}", path.display()))
+                            }
+                        }
+                    }
+                    if ui.button("Load .kbm").clicked()
+                        && let Some(path) = FileDialog::new()
+                            .add_filter("Scala keyboard mapping", &["kbm"])
+                            .pick_file()
+                    {
+                        match Tuning::load_kbm(&path) {
+                            Ok(mapping) => {
+                                self.settings.concert_pitch = mapping.reference_frequency as f32;
+                                self.settings.tuning.set_mapping(&file_stem(&path), mapping);
+                                let notes = self.selected_notes();
+                                self.load_warnings
+                                    .extend(self.settings.silent_key_warning(&notes));
+                            }
+                            Err(e) => {
+                                self.load_error =
+                                    Some(format!("Could not load {}: {e}", path.display()))
+                            }
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let scale = self.speed_unit.mm_per_unit();
+                    let mut speed = self.settings.drag_speed / scale;
+                    if ui
+                        .add(
+                            egui::DragValue::new(&mut speed)
+                                .range(1.0 / scale..=2000.0 / scale)
+                                .speed(1.0 / scale)
+                                .prefix("Drag Speed: "),
+                        )
+                        .changed()
+                    {
+                        self.settings.drag_speed = speed * scale;
+                    }
+                    egui::ComboBox::from_id_salt("speed_unit")
+                        .selected_text(self.speed_unit.label())
+                        .show_ui(ui, |ui| {
+                            for unit in [
+                                SpeedUnit::MillimetersPerSecond,
+                                SpeedUnit::CentimetersPerSecond,
+                            ] {
+                                ui.selectable_value(&mut self.speed_unit, unit, unit.label());
+                            }
+                        });
+                });
+                ui.label(match self.settings.calculate_spacing(69.0) {
+                    Some(spacing) => format!("A4 tooth pitch: {spacing:.3} mm"),
+                    None => "A4 is silent in this tuning".to_string(),
+                });
 
-            ui.separator();
-            ui.label("Physics Calibration");
-            ui.add(egui::Slider::new(&mut self.ref_note, 0..=127).text("Ref Note (MIDI)"));
-            ui.add(egui::Slider::new(&mut self.ref_spacing, 0.5..=50.0).text("Ref Spacing (px)"));
-            ui.add(egui::Slider::new(&mut self.px_per_beat, 10.0..=2000.0).text("Pixels per Beat"));
+                ui.separator();
+                ui.label("Tooth Geometry");
+                let shape = &mut self.settings.tooth_shape;
+                egui::ComboBox::from_label("Profile")
+                    .selected_text(shape.profile.label())
+                    .show_ui(ui, |ui| {
+                        for profile in ToothProfile::ALL {
+                            ui.selectable_value(&mut shape.profile, profile, profile.label());
+                        }
+                    });
+                ui.add(
+                    egui::Slider::new(&mut shape.duty_cycle, 0.05..=0.95)
+                        .text("Duty Cycle (tooth / pitch)"),
+                );
+                ui.add(egui::Slider::new(&mut shape.depth, 0.5..=50.0).text("Tooth Depth (mm)"));
+                ui.add(egui::Slider::new(&mut shape.spine, 0.5..=50.0).text("Spine Height (mm)"));
+                egui::ComboBox::from_label("Tooth Placement")
+                    .selected_text(self.settings.tooth_placement.label())
+                    .show_ui(ui, |ui| {
+                        for placement in ToothPlacement::ALL {
+                            ui.selectable_value(
+                                &mut self.settings.tooth_placement,
+                                placement,
+                                placement.label(),
+                            );
+                        }
+                    });
 
-            ui.separator();
-            ui.label("Timeline View");
-            if ui.button("⏮ Jump to Start of Notes").clicked() {
-                if let Some(tracks) = &self.tracks {
-                    if let Some(track) = tracks.get(self.selected_track) {
-                        if let Some(first_note) = track.notes.first() {
-                            self.scroll_to = Some(first_note.start_time * self.px_per_beat - 50.0);
+                ui.separator();
+                ui.label("Rests");
+                egui::ComboBox::from_label("Between Notes")
+                    .selected_text(self.settings.rest_treatment.label())
+                    .show_ui(ui, |ui| {
+                        for treatment in RestTreatment::ALL {
+                            ui.selectable_value(
+                                &mut self.settings.rest_treatment,
+                                treatment,
+                                treatment.label(),
+                            );
+                        }
+                    });
+                ui.add(
+                    egui::Slider::new(&mut self.settings.min_rest_ms, 0.0..=500.0)
+                        .text("Minimum Rest (ms)"),
+                )
+                .on_hover_text("Shorter rests are filled by holding the note before them");
+
+                ui.separator();
+                ui.label("Repeated Notes");
+                egui::ComboBox::from_label("Articulation")
+                    .selected_text(self.settings.articulation.label())
+                    .show_ui(ui, |ui| {
+                        for articulation in Articulation::ALL {
+                            ui.selectable_value(
+                                &mut self.settings.articulation,
+                                articulation,
+                                articulation.label(),
+                            );
+                        }
+                    });
+                if self.settings.articulation == Articulation::Gap {
+                    ui.horizontal(|ui| {
+                        let unit = &mut self.settings.articulation_unit;
+                        let (speed, max) = match unit {
+                            DurationUnit::Milliseconds => (1.0, 500.0),
+                            DurationUnit::Beats => (0.005, 1.0),
+                        };
+                        ui.add(
+                            egui::DragValue::new(&mut self.settings.articulation_length)
+                                .speed(speed)
+                                .range(0.0..=max),
+                        );
+                        egui::ComboBox::from_id_salt("articulation_unit")
+                            .selected_text(unit.label())
+                            .show_ui(ui, |ui| {
+                                for option in [DurationUnit::Milliseconds, DurationUnit::Beats] {
+                                    ui.selectable_value(unit, option, option.label());
+                                }
+                            });
+                        ui.label("Gap Length");
+                    });
+                }
+
+                ui.separator();
+                ui.label("Chords");
+                egui::ComboBox::from_label("Chord Reduction")
+                    .selected_text(self.settings.chord_strategy.label())
+                    .show_ui(ui, |ui| {
+                        for strategy in ChordStrategy::ALL {
+                            ui.selectable_value(
+                                &mut self.settings.chord_strategy,
+                                strategy,
+                                strategy.label(),
+                            );
                         }
+                    });
+                if self.settings.chord_strategy == ChordStrategy::Arpeggiate {
+                    ui.add(
+                        egui::Slider::new(
+                            &mut self.settings.arpeggio_slice_ms,
+                            CombSettings::MIN_ARPEGGIO_SLICE_MS..=500.0,
+                        )
+                        .text("Arpeggio Slice (ms)"),
+                    );
+                }
+
+                ui.checkbox(
+                    &mut self.settings.multi_lane,
+                    "Polyphonic lanes (one row per voice)",
+                );
+                if self.settings.multi_lane {
+                    ui.add(
+                        egui::Slider::new(&mut self.settings.max_lanes, 1..=8).text("Max Lanes"),
+                    );
+                    egui::ComboBox::from_label("When too many voices")
+                        .selected_text(self.settings.voice_drop_rule.label())
+                        .show_ui(ui, |ui| {
+                            for rule in VoiceDropRule::ALL {
+                                ui.selectable_value(
+                                    &mut self.settings.voice_drop_rule,
+                                    rule,
+                                    rule.label(),
+                                );
+                            }
+                        });
+                    ui.add(
+                        egui::Slider::new(&mut self.settings.lane_gap, 0.0..=20.0)
+                            .text("Lane Gap (mm)"),
+                    );
+                }
+
+                ui.separator();
+                ui.label("Timing");
+                egui::ComboBox::from_label("Layout")
+                    .selected_text(self.settings.layout_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in [LayoutMode::Seconds, LayoutMode::Beats] {
+                            ui.selectable_value(&mut self.settings.layout_mode, mode, mode.label());
+                        }
+                    });
+                if self.settings.layout_mode == LayoutMode::Beats {
+                    ui.add(
+                        egui::Slider::new(&mut self.settings.playing_bpm, 20.0..=300.0)
+                            .text("Playing Tempo (BPM)"),
+                    );
+                }
+                if self.tracks.is_some() {
+                    ui.label(format!("Initial tempo: {:.1} BPM", self.initial_bpm));
+                    if self.has_tempo_changes {
+                        ui.label("⚠ Tempo changes present");
                     }
                 }
-            }
-            let mut dv_offset = self.scroll_offset;
-            if ui
-                .add(
-                    egui::DragValue::new(&mut dv_offset)
-                        .prefix("Scroll X: ")
-                        .speed(5.0),
-                )
-                .changed()
-            {
-                self.scroll_to = Some(dv_offset);
-            }
 
-            ui.separator();
-            if ui.button("🖼 Export SVG").clicked() {
-                if let Some(path) = FileDialog::new()
-                    .set_file_name("comb_pattern.svg")
-                    .save_file()
+                ui.separator();
+                ui.label("Timeline View");
+                ui.add(
+                    egui::Slider::new(&mut self.px_per_mm, 0.5..=100.0)
+                        .logarithmic(true)
+                        .text("Zoom (px/mm)"),
+                );
+                egui::ComboBox::from_label("Compare Placement")
+                    .selected_text(self.compare_placement.map_or("Off", |p| p.label()))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.compare_placement, None, "Off");
+                        for placement in ToothPlacement::ALL {
+                            ui.selectable_value(
+                                &mut self.compare_placement,
+                                Some(placement),
+                                placement.label(),
+                            );
+                        }
+                    });
+                if ui.button("⏮ Jump to Start of Notes").clicked()
+                    && let Some(first_note) = self.selected_notes().first()
                 {
-                    let content = self.generate_svg();
-                    let _ = fs::write(path, content);
-                    self.export_status = "SVG Exported successfully.".to_string();
+                    let (start, _) = self.settings.note_span(first_note);
+                    self.scroll_to = Some(start * self.px_per_unit() - 50.0);
                 }
-            }
+                let mut dv_offset = self.scroll_offset;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut dv_offset)
+                            .prefix("Scroll X: ")
+                            .speed(5.0),
+                    )
+                    .changed()
+                {
+                    self.scroll_to = Some(dv_offset);
+                }
+
+                ui.separator();
+                if ui.button("🖼 Export SVG").clicked() {
+                    let notes = self.selected_notes();
+                    match self.settings.generate_svg(&notes) {
+                        Ok(content) => {
+                            if let Some(path) = FileDialog::new()
+                                .set_file_name("comb_pattern.svg")
+                                .save_file()
+                                && self.write_export(&path, content, "SVG")
+                                && let Some(warning) =
+                                    self.layout_cache.kerf_warning(&self.settings, &notes)
+                            {
+                                self.export_status.push('\n');
+                                self.export_status.push_str(&warning);
+                            }
+                        }
+                        Err(message) => self.export_status = message.to_string(),
+                    }
+                }
+                if ui.button("📐 Export DXF").clicked() {
+                    let notes = self.selected_notes();
+                    match self.settings.generate_dxf(&notes) {
+                        Ok(content) => {
+                            if let Some(path) = FileDialog::new()
+                                .set_file_name("comb_pattern.dxf")
+                                .save_file()
+                                && self.write_export(&path, content, "DXF")
+                                && let Some(warning) =
+                                    self.layout_cache.kerf_warning(&self.settings, &notes)
+                            {
+                                self.export_status.push('\n');
+                                self.export_status.push_str(&warning);
+                            }
+                        }
+                        Err(message) => self.export_status = message.to_string(),
+                    }
+                }
+                egui::CollapsingHeader::new("Strips").show(ui, |ui| {
+                    let mut split = self.settings.max_strip_length > 0.0;
+                    if ui.checkbox(&mut split, "Split into pieces").changed() {
+                        self.settings.max_strip_length = if split { 300.0 } else { 0.0 };
+                    }
+                    if split {
+                        ui.add(
+                            egui::Slider::new(&mut self.settings.max_strip_length, 50.0..=2000.0)
+                                .text("Max Strip Length (mm)"),
+                        );
+                        egui::ComboBox::from_label("Joint")
+                            .selected_text(self.settings.strip_joint.label())
+                            .show_ui(ui, |ui| {
+                                for joint in StripJoint::ALL {
+                                    ui.selectable_value(
+                                        &mut self.settings.strip_joint,
+                                        joint,
+                                        joint.label(),
+                                    );
+                                }
+                            });
+                        let notes = self.selected_notes();
+                        if let Some(layout) = self.layout_cache.layout(&self.settings, &notes) {
+                            match self.settings.strip_breaks(&layout) {
+                                Ok(breaks) => {
+                                    ui.label(format!("{} pieces", breaks.len() + 1));
+                                }
+                                Err(e) => {
+                                    ui.colored_label(egui::Color32::YELLOW, e.to_string());
+                                }
+                            }
+                        }
+                    }
+                });
+                egui::CollapsingHeader::new("Nesting (SVG/DXF)").show(ui, |ui| {
+                    let n = &mut self.settings.nest_settings;
+                    ui.add(
+                        egui::DragValue::new(&mut n.sheet_width)
+                            .range(10.0..=5000.0)
+                            .prefix("Sheet Width: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut n.sheet_height)
+                            .range(10.0..=5000.0)
+                            .prefix("Sheet Height: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut n.spacing)
+                            .range(0.0..=50.0)
+                            .speed(0.1)
+                            .prefix("Spacing: ")
+                            .suffix(" mm"),
+                    );
+                    ui.checkbox(&mut n.per_track, "One comb per track");
+                    ui.horizontal(|ui| {
+                        for (label, extension) in [
+                            ("🗺 Export Nested SVG", "svg"),
+                            ("🗺 Export Nested DXF", "dxf"),
+                        ] {
+                            if !ui.button(label).clicked() {
+                                continue;
+                            }
+                            let combs: Vec<Vec<MidiNote>> = if self.settings.nest_settings.per_track
+                            {
+                                self.selected_parts()
+                                    .into_iter()
+                                    .map(|(_, notes)| notes)
+                                    .collect()
+                            } else {
+                                vec![self.selected_notes()]
+                            };
+                            match self.settings.nest_combs(&combs) {
+                                Ok(nesting) => {
+                                    if let Some(path) = FileDialog::new()
+                                        .set_file_name(format!("comb_sheets.{extension}"))
+                                        .save_file()
+                                    {
+                                        let content = if extension == "svg" {
+                                            self.settings.generate_nested_svg(&nesting)
+                                        } else {
+                                            self.settings.generate_nested_dxf(&nesting)
+                                        };
+                                        if self.write_export(&path, content, "Sheets") {
+                                            self.export_status.push('\n');
+                                            self.export_status.push_str(&nesting.report());
+                                        }
+                                    }
+                                }
+                                Err(message) => self.export_status = message.to_string(),
+                            }
+                        }
+                    });
+                });
+                egui::CollapsingHeader::new("Laser Kerf (SVG/DXF)").show(ui, |ui| {
+                    ui.add(egui::Slider::new(&mut self.settings.kerf, 0.0..=1.0).text("Kerf (mm)"));
+                    egui::ComboBox::from_label("Offset")
+                        .selected_text(self.settings.kerf_direction.label())
+                        .show_ui(ui, |ui| {
+                            for direction in [KerfDirection::Outward, KerfDirection::Inward] {
+                                ui.selectable_value(
+                                    &mut self.settings.kerf_direction,
+                                    direction,
+                                    direction.label(),
+                                );
+                            }
+                        });
+                    ui.add(
+                        egui::Slider::new(&mut self.settings.min_feature_width, 0.01..=2.0)
+                            .text("Min Feature Width (mm)"),
+                    );
+                    let notes = self.selected_notes();
+                    if let Some(warning) = self.layout_cache.kerf_warning(&self.settings, &notes) {
+                        ui.colored_label(egui::Color32::YELLOW, warning);
+                    }
+                });
+                egui::CollapsingHeader::new("CNC (G-code)").show(ui, |ui| {
+                    let g = &mut self.settings.gcode_settings;
+                    ui.add(
+                        egui::DragValue::new(&mut g.tool_diameter)
+                            .range(0.05..=10.0)
+                            .speed(0.01)
+                            .prefix("Tool Ø: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut g.feed_rate)
+                            .range(1.0..=5000.0)
+                            .prefix("Feed: ")
+                            .suffix(" mm/min"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut g.plunge_rate)
+                            .range(1.0..=2000.0)
+                            .prefix("Plunge: ")
+                            .suffix(" mm/min"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut g.cut_depth)
+                            .range(0.05..=20.0)
+                            .speed(0.05)
+                            .prefix("Depth: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut g.passes)
+                            .range(1..=50)
+                            .prefix("Passes: "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut g.safe_z)
+                            .range(0.5..=50.0)
+                            .speed(0.1)
+                            .prefix("Safe Z: ")
+                            .suffix(" mm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut g.spindle_rpm)
+                            .range(0..=60000)
+                            .speed(100.0)
+                            .prefix("Spindle: ")
+                            .suffix(" rpm"),
+                    );
+                    if ui.button("🛠 Export G-code").clicked() {
+                        match self.settings.generate_gcode(&self.selected_notes()) {
+                            Ok(content) => {
+                                if let Some(path) = FileDialog::new()
+                                    .set_file_name("comb_pattern.nc")
+                                    .save_file()
+                                {
+                                    self.write_export(&path, content, "G-code");
+                                }
+                            }
+                            Err(message) => self.export_status = message.to_string(),
+                        }
+                    }
+                });
+                egui::CollapsingHeader::new("Bitmap (PNG/TIFF)").show(ui, |ui| {
+                    let r = &mut self.settings.raster_settings;
+                    ui.add(
+                        egui::DragValue::new(&mut r.dpi)
+                            .range(50..=4800)
+                            .speed(10.0)
+                            .prefix("Resolution: ")
+                            .suffix(" dpi"),
+                    );
+                    ui.checkbox(&mut r.one_bit, "1-bit (black and white only)");
+                    ui.checkbox(&mut r.crop_marks, "Crop marks");
+                    ui.horizontal(|ui| {
+                        for (label, format) in [
+                            ("🖼 Export PNG", RasterFormat::Png),
+                            ("🖼 Export TIFF", RasterFormat::Tiff),
+                        ] {
+                            if !ui.button(label).clicked() {
+                                continue;
+                            }
+                            match self
+                                .settings
+                                .generate_raster(&self.selected_notes(), format)
+                            {
+                                Ok(content) => {
+                                    if let Some(path) = FileDialog::new()
+                                        .set_file_name(format!(
+                                            "comb_pattern.{}",
+                                            format.extension()
+                                        ))
+                                        .save_file()
+                                    {
+                                        self.write_export(&path, content, "Bitmap");
+                                    }
+                                }
+                                Err(message) => self.export_status = message.to_string(),
+                            }
+                        }
+                    });
+                });
+                egui::CollapsingHeader::new("Printout (PDF)").show(ui, |ui| {
+                    let p = &mut self.settings.pdf_settings;
+                    egui::ComboBox::from_label("Paper")
+                        .selected_text(p.paper.label())
+                        .show_ui(ui, |ui| {
+                            for paper in PaperSize::ALL {
+                                ui.selectable_value(&mut p.paper, paper, paper.label());
+                            }
+                        });
+                    ui.checkbox(&mut p.landscape, "Landscape");
+                    ui.add(
+                        egui::DragValue::new(&mut p.overlap)
+                            .range(0.0..=50.0)
+                            .speed(0.5)
+                            .prefix("Overlap: ")
+                            .suffix(" mm"),
+                    );
+                    if ui.button("📄 Export PDF").clicked() {
+                        let song = file_stem(Path::new(&self.file_path));
+                        let tracks: Vec<String> =
+                            self.tracks.as_ref().map_or_else(Vec::new, |tracks| {
+                                self.selected_tracks
+                                    .iter()
+                                    .filter_map(|&i| tracks.get(i))
+                                    .map(|track| track.name.clone())
+                                    .collect()
+                            });
+                        match self
+                            .settings
+                            .generate_pdf(&self.selected_notes(), &song, &tracks)
+                        {
+                            Ok(content) => {
+                                if let Some(path) = FileDialog::new()
+                                    .set_file_name("comb_pattern.pdf")
+                                    .save_file()
+                                {
+                                    self.write_export(&path, content, "PDF");
+                                }
+                            }
+                            Err(message) => self.export_status = message.to_string(),
+                        }
+                    }
+                });
 
-            ui.label(&self.export_status);
+                ui.label(&self.export_status);
+            });
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
             // Determine total width needed for the timeline
+            let parts = self.selected_parts();
+            let notes = merge_notes(parts.iter().map(|(_, notes)| notes.as_slice()));
             let mut total_width = ui.available_width();
-            if let Some(tracks) = &self.tracks {
-                if let Some(track_data) = tracks.get(self.selected_track) {
-                    let max_end_time = track_data
-                        .notes
-                        .iter()
-                        .map(|n| n.start_time + n.duration)
-                        .fold(0.0, f32::max);
-                    let end_x = max_end_time * self.px_per_beat;
-                    total_width = total_width.max(end_x + 100.0);
-                }
+            if !notes.is_empty() {
+                let max_end_time = notes
+                    .iter()
+                    .map(|n| self.settings.note_span(n).1)
+                    .fold(0.0, f32::max);
+                let end_x = max_end_time * self.px_per_unit();
+                total_width = total_width.max(end_x + 100.0);
             }
 
             let mut scroll_area = egui::ScrollArea::horizontal();
@@ -386,145 +1136,160 @@ impl eframe::App for MidiVisualizer {
 
                 painter.rect_filled(rect, 0.0, egui::Color32::from_rgb(20, 20, 25));
 
-                if let Some(tracks) = &self.tracks {
-                    if let Some(track_data) = tracks.get(self.selected_track) {
-                        let segments = self.get_comb_segments();
-                        for segment in &segments {
-                            let start_x_abs = segment.start_time * self.px_per_beat;
-                            let end_x_abs = segment.end_time * self.px_per_beat;
-                            let spacing = segment.spacing;
-
-                            if spacing > 0.1 {
-                                let first_tooth_index = (start_x_abs / spacing).ceil() as i64;
-                                let mut current_x_abs = first_tooth_index as f32 * spacing;
-
-                                while current_x_abs < end_x_abs {
-                                    let current_x_screen = rect.min.x + current_x_abs;
-                                    if ui.clip_rect().x_range().contains(current_x_screen) {
-                                        painter.line_segment(
-                                            [
-                                                egui::pos2(
-                                                    current_x_screen,
-                                                    rect.center().y - 60.0,
-                                                ),
-                                                egui::pos2(
-                                                    current_x_screen,
-                                                    rect.center().y + 60.0,
-                                                ),
-                                            ],
-                                            egui::Stroke::new(
-                                                1.2,
-                                                egui::Color32::from_rgb(0, 255, 200),
-                                            ),
-                                        );
-                                    }
-                                    current_x_abs += spacing;
-                                }
+                if self.tracks.is_some() {
+                    // Each comb is drawn to scale horizontally and squeezed into a fixed
+                    // band vertically; a placement being compared goes above the real one
+                    let clip = ui.clip_rect();
+                    let band_top = rect.center().y - 60.0;
+                    if let Some(layout) = self.layout_cache.layout(&self.settings, &notes) {
+                        let origin = egui::pos2(rect.min.x, band_top);
+                        let color = egui::Color32::from_rgb(0, 255, 200);
+                        draw_comb(&painter, &layout, origin, self.px_per_mm, clip, color);
+                        // Where the comb is split, numbered by the piece that starts there
+                        let cut = egui::Color32::from_rgb(255, 90, 90);
+                        for (i, x) in self
+                            .settings
+                            .strip_breaks(&layout)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .enumerate()
+                        {
+                            let x = origin.x + (x + layout.x_offset) * self.px_per_mm;
+                            painter.line_segment(
+                                [
+                                    egui::pos2(x, band_top - 10.0),
+                                    egui::pos2(x, band_top + 130.0),
+                                ],
+                                egui::Stroke::new(1.0, cut),
+                            );
+                            painter.text(
+                                egui::pos2(x + 3.0, band_top - 10.0),
+                                egui::Align2::LEFT_BOTTOM,
+                                format!("✂ {}", i + 2),
+                                egui::FontId::proportional(12.0),
+                                cut,
+                            );
+                        }
+                    }
+                    if let Some(placement) = self.compare_placement
+                        && placement != self.settings.tooth_placement
+                    {
+                        let settings = CombSettings {
+                            tooth_placement: placement,
+                            ..self.settings.clone()
+                        };
+                        if let Some(layout) = self.compare_cache.layout(&settings, &notes) {
+                            let origin = egui::pos2(rect.min.x, band_top - 150.0);
+                            let color = egui::Color32::from_rgb(255, 170, 80);
+                            draw_comb(&painter, &layout, origin, self.px_per_mm, clip, color);
+                            for (y, placement, color) in [
+                                (origin.y, placement, color),
+                                (band_top, self.settings.tooth_placement, egui::Color32::GRAY),
+                            ] {
+                                painter.text(
+                                    egui::pos2(clip.left() + 8.0, y - 4.0),
+                                    egui::Align2::LEFT_BOTTOM,
+                                    placement.label(),
+                                    egui::FontId::proportional(12.0),
+                                    color,
+                                );
                             }
                         }
+                    }
 
-                        // Draw note labels with vertical layout to avoid overlap
-                        {
-                            struct NoteWithLane<'a> {
-                                note: &'a MidiNote,
-                                lane: usize,
-                            }
-                            let mut notes_with_lanes: Vec<NoteWithLane> =
-                                Vec::with_capacity(track_data.notes.len());
-                            let mut lane_end_times: Vec<f32> = Vec::new();
-
-                            for note in &track_data.notes {
-                                let mut placed_in_lane = None;
-                                for (i, end_time) in lane_end_times.iter_mut().enumerate() {
-                                    if note.start_time >= *end_time {
-                                        placed_in_lane = Some(i);
-                                        *end_time = note.start_time + note.duration;
-                                        break;
-                                    }
-                                }
+                    // Draw note labels with vertical layout to avoid overlap. Merged tracks
+                    // keep every source note here, each in its track's colour.
+                    {
+                        let mut labelled: Vec<(usize, &MidiNote)> = parts
+                            .iter()
+                            .flat_map(|(track, notes)| notes.iter().map(move |n| (*track, n)))
+                            .collect();
+                        labelled.sort_by(|a, b| a.1.start_time.total_cmp(&b.1.start_time));
+                        let merging = parts.len() > 1;
 
-                                let lane = match placed_in_lane {
-                                    Some(i) => i,
-                                    None => {
-                                        let new_lane_index = lane_end_times.len();
-                                        lane_end_times.push(note.start_time + note.duration);
-                                        new_lane_index
-                                    }
-                                };
-                                notes_with_lanes.push(NoteWithLane { note, lane });
-                            }
+                        let spans: Vec<(f32, f32)> = labelled
+                            .iter()
+                            .map(|(_, n)| self.settings.note_span(n))
+                            .collect();
+                        let order: Vec<usize> = (0..spans.len()).collect();
+                        let label_lanes = pack_lanes(&spans, &order, None);
 
-                            let y_base = rect.center().y + 80.0;
-                            let lane_height = 15.0;
-                            let marker_height = 10.0;
+                        let y_base = rect.center().y + 80.0;
+                        let lane_height = 15.0;
+                        let marker_height = 10.0;
 
-                            for item in &notes_with_lanes {
-                                let note = item.note;
-                                let lane = item.lane;
-                                let y_pos = y_base + (lane as f32 * lane_height);
+                        for (&(track, note), lane) in labelled.iter().zip(label_lanes) {
+                            let lane = lane.unwrap_or(0);
+                            let (line_color, text_color) = if merging {
+                                let color = track_color(track);
+                                (color.gamma_multiply(0.6), color)
+                            } else {
+                                (egui::Color32::from_gray(100), egui::Color32::from_gray(200))
+                            };
+                            let y_pos = y_base + (lane as f32 * lane_height);
 
-                                let start_x_abs = note.start_time * self.px_per_beat;
-                                let end_x_abs =
-                                    (note.start_time + note.duration) * self.px_per_beat;
+                            let (note_start, note_end) = self.settings.note_span(note);
+                            let start_x_abs = note_start * self.px_per_unit();
+                            let end_x_abs = note_end * self.px_per_unit();
 
-                                let start_x_screen = rect.min.x + start_x_abs;
-                                let end_x_screen = rect.min.x + end_x_abs;
+                            let start_x_screen = rect.min.x + start_x_abs;
+                            let end_x_screen = rect.min.x + end_x_abs;
 
-                                if end_x_screen < ui.clip_rect().left()
-                                    || start_x_screen > ui.clip_rect().right()
-                                {
-                                    continue;
-                                }
+                            if end_x_screen < ui.clip_rect().left()
+                                || start_x_screen > ui.clip_rect().right()
+                            {
+                                continue;
+                            }
 
-                                // Draw start and end markers
-                                painter.line_segment(
-                                    [
-                                        egui::pos2(start_x_screen, y_pos - marker_height / 2.0),
-                                        egui::pos2(start_x_screen, y_pos + marker_height / 2.0),
-                                    ],
-                                    egui::Stroke::new(1.0, egui::Color32::from_gray(100)),
-                                );
-                                painter.line_segment(
-                                    [
-                                        egui::pos2(end_x_screen, y_pos - marker_height / 2.0),
-                                        egui::pos2(end_x_screen, y_pos + marker_height / 2.0),
-                                    ],
-                                    egui::Stroke::new(1.0, egui::Color32::from_gray(100)),
-                                );
+                            // Draw start and end markers
+                            painter.line_segment(
+                                [
+                                    egui::pos2(start_x_screen, y_pos - marker_height / 2.0),
+                                    egui::pos2(start_x_screen, y_pos + marker_height / 2.0),
+                                ],
+                                egui::Stroke::new(1.0, line_color),
+                            );
+                            painter.line_segment(
+                                [
+                                    egui::pos2(end_x_screen, y_pos - marker_height / 2.0),
+                                    egui::pos2(end_x_screen, y_pos + marker_height / 2.0),
+                                ],
+                                egui::Stroke::new(1.0, line_color),
+                            );
 
-                                // Draw horizontal line for the note duration
-                                painter.line_segment(
-                                    [
-                                        egui::pos2(start_x_screen, y_pos),
-                                        egui::pos2(end_x_screen, y_pos),
-                                    ],
-                                    egui::Stroke::new(0.5, egui::Color32::from_gray(100)),
-                                );
+                            // Draw horizontal line for the note duration
+                            painter.line_segment(
+                                [
+                                    egui::pos2(start_x_screen, y_pos),
+                                    egui::pos2(end_x_screen, y_pos),
+                                ],
+                                egui::Stroke::new(0.5, line_color),
+                            );
 
-                                let note_name = self.midi_pitch_to_name(note.pitch);
-                                let note_width_px = end_x_screen - start_x_screen;
-
-                                // Only draw text if it fits, to avoid clutter
-                                if note_width_px > note_name.len() as f32 * 7.0 + 4.0 {
-                                    painter.text(
-                                        egui::pos2((start_x_screen + end_x_screen) / 2.0, y_pos),
-                                        egui::Align2::CENTER_CENTER,
-                                        note_name,
-                                        egui::FontId::proportional(12.0),
-                                        egui::Color32::from_gray(200),
-                                    );
-                                }
+                            let note_name = midi_pitch_to_name(note.pitch);
+                            let note_width_px = end_x_screen - start_x_screen;
+
+                            // Only draw text if it fits, to avoid clutter
+                            if note_width_px > note_name.len() as f32 * 7.0 + 4.0 {
+                                painter.text(
+                                    egui::pos2((start_x_screen + end_x_screen) / 2.0, y_pos),
+                                    egui::Align2::CENTER_CENTER,
+                                    note_name,
+                                    egui::FontId::proportional(12.0),
+                                    text_color,
+                                );
                             }
                         }
-                        // Draw a visual reference line
-                        painter.line_segment(
-                            [
-                                egui::pos2(rect.min.x, rect.center().y + 60.0),
-                                egui::pos2(rect.max.x, rect.center().y + 60.0),
-                            ],
-                            egui::Stroke::new(1.0, egui::Color32::GRAY),
-                        );
                     }
+                    // Draw a visual reference line
+                    painter.line_segment(
+                        [
+                            egui::pos2(rect.min.x, rect.center().y + 60.0),
+                            egui::pos2(rect.max.x, rect.center().y + 60.0),
+                        ],
+                        egui::Stroke::new(1.0, egui::Color32::GRAY),
+                    );
+                    self.draw_ruler(&painter, rect, ui.clip_rect());
                 } else {
                     ui.centered_and_justified(|ui| {
                         ui.label("Please load a MIDI file to generate patterns.");
@@ -535,7 +1300,95 @@ impl eframe::App for MidiVisualizer {
     }
 }
 
+fn file_stem(path: &std::path::Path) -> String {
+    path.file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+}
+
+/// Label colour of a source track when several are merged.
+fn track_color(track: usize) -> egui::Color32 {
+    const COLORS: [egui::Color32; 6] = [
+        egui::Color32::from_rgb(255, 170, 80),
+        egui::Color32::from_rgb(110, 180, 255),
+        egui::Color32::from_rgb(240, 110, 200),
+        egui::Color32::from_rgb(150, 230, 110),
+        egui::Color32::from_rgb(250, 230, 100),
+        egui::Color32::from_rgb(180, 140, 255),
+    ];
+    COLORS[track % COLORS.len()]
+}
+
+/// Draws every row of `layout` with its song start at `origin.x` and its top at `origin.y`,
+/// 120 px high whatever its size, shading the rests. Only the part inside `clip` is drawn.
+fn draw_comb(
+    painter: &egui::Painter,
+    layout: &CombLayout,
+    origin: egui::Pos2,
+    px_per_mm: f32,
+    clip: egui::Rect,
+    color: egui::Color32,
+) {
+    let y_scale = 120.0 / layout.height;
+    let to_screen = |&(x, y): &geometry::Point| {
+        egui::pos2(
+            origin.x + (x + layout.x_offset) * px_per_mm,
+            origin.y + y * y_scale,
+        )
+    };
+    let stroke = egui::Stroke::new(1.2, color);
+
+    for lane in &layout.lanes {
+        for &(left, right) in &lane.rests {
+            let rest = egui::Rect::from_min_max(
+                to_screen(&(left, lane.top)),
+                to_screen(&(right, lane.bottom)),
+            );
+            if rest.intersects(clip) {
+                painter.rect_filled(rest, 0.0, egui::Color32::from_white_alpha(12));
+            }
+        }
+        // Outline x only ever grows between the two end caps
+        let visible_x = |p: &geometry::Point| to_screen(p).x;
+        let first = lane
+            .outline
+            .partition_point(|p| visible_x(p) < clip.left())
+            .saturating_sub(1);
+        let last = lane
+            .outline
+            .partition_point(|p| visible_x(p) <= clip.right())
+            .min(lane.outline.len() - 1);
+        let points: Vec<egui::Pos2> = lane.outline[first..=last].iter().map(to_screen).collect();
+        // Back edge of the spine, which closes the outline
+        painter.line_segment(
+            [
+                to_screen(&lane.outline[0]),
+                to_screen(&lane.outline[lane.outline.len() - 1]),
+            ],
+            stroke,
+        );
+        painter.add(egui::Shape::line(points, stroke));
+    }
+}
+
+/// Framed list of messages with a dismiss button. Returns true once dismissed.
+fn notice_panel(ui: &mut egui::Ui, color: egui::Color32, lines: &[String]) -> bool {
+    egui::Frame::group(ui.style())
+        .stroke(egui::Stroke::new(1.0, color))
+        .show(ui, |ui| {
+            for line in lines {
+                ui.colored_label(color, format!("⚠ {line}"));
+            }
+            ui.button("Dismiss").clicked()
+        })
+        .inner
+}
+
 fn main() -> eframe::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(midi_to_bar::cli::run(args));
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([1000.0, 600.0]),
         ..Default::default()
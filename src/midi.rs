@@ -0,0 +1,918 @@
+//! MIDI parsing: turns a Standard MIDI File into note lists timed in beats and seconds.
+
+use crate::gm;
+use midly::{MetaMessage, Smf, TrackEventKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MidiNote {
+    pub pitch: u8,
+    pub start_time: f32, // In beats
+    pub duration: f32,   // In beats
+    pub start_seconds: f32,
+    pub duration_seconds: f32,
+    /// Pitch bend while the note sounds, in time order. Each point holds until the next;
+    /// the first sits at the note start when the note begins already bent. Empty when the
+    /// note is never bent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bend: Vec<BendPoint>,
+    /// Struck with the soft (una corda) pedal down. A comb cannot play quieter, so this is
+    /// only shown to help decide which notes to keep.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub soft: bool,
+}
+
+/// A pitch bend taking effect, at an absolute position in the song.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BendPoint {
+    pub beat: f32,
+    pub seconds: f32,
+    pub semitones: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackData {
+    pub name: String,
+    pub notes: Vec<MidiNote>,
+}
+
+impl TrackData {
+    /// A track from notes in any order; they are sorted by start, then pitch.
+    pub fn new(name: String, mut notes: Vec<MidiNote>) -> Self {
+        notes.sort_by(|a, b| {
+            a.start_time
+                .total_cmp(&b.start_time)
+                .then(a.pitch.cmp(&b.pitch))
+        });
+        Self { name, notes }
+    }
+}
+
+/// Union of several note lists, sorted like a track. A note struck at the same time and
+/// pitch in more than one list (a unison between two hands, say) is kept once, with the
+/// longest duration.
+pub fn merge_notes<'a>(parts: impl IntoIterator<Item = &'a [MidiNote]>) -> Vec<MidiNote> {
+    let mut merged: Vec<MidiNote> = parts.into_iter().flatten().cloned().collect();
+    merged.sort_by(|a, b| {
+        a.start_time
+            .total_cmp(&b.start_time)
+            .then(a.pitch.cmp(&b.pitch))
+            .then(b.duration.total_cmp(&a.duration))
+    });
+    merged.dedup_by(|later, kept| later.pitch == kept.pitch && later.start_time == kept.start_time);
+    merged
+}
+
+/// Every note track of a file, plus what the tempo map says about it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Song {
+    pub tracks: Vec<TrackData>,
+    /// Tempo at the start of the file, in beats per minute.
+    pub initial_bpm: f32,
+    pub has_tempo_changes: bool,
+    /// Problems found while reading that did not stop the load, one line each.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Choices about how a file is turned into tracks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoadOptions {
+    /// Turn each track that plays on several channels into one track per channel, as most
+    /// format 0 files put every instrument in a single track.
+    pub split_channels: bool,
+    /// Keep General MIDI percussion (channel 10) when splitting. Drum notes are keys, not
+    /// pitches, so they are left out by default.
+    pub include_percussion: bool,
+    /// Keep notes sounding while the sustain or sostenuto pedal holds them, ending them
+    /// when the pedal lifts instead of when the key is released.
+    pub sustain_pedal: bool,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Parse(midly::Error),
+    /// The header asks for a time base that cannot be turned into beats and seconds.
+    UnsupportedTiming(&'static str),
+    NoNoteTracks,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "could not read file: {e}"),
+            LoadError::Parse(e) => write!(f, "not a valid MIDI file: {e}"),
+            LoadError::UnsupportedTiming(reason) => write!(f, "unsupported timing: {reason}"),
+            LoadError::NoNoteTracks => write!(f, "no tracks with notes found"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Parse(e) => Some(e),
+            LoadError::UnsupportedTiming(_) | LoadError::NoNoteTracks => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<midly::Error> for LoadError {
+    fn from(e: midly::Error) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+/// Pitch bend state of one channel, with every bend it has played so far.
+struct ChannelBend {
+    /// Bend wheel position, -1.0 to just under 1.0.
+    position: f32,
+    /// Semitones at full deflection, set through RPN 0.
+    range: f32,
+    /// Registered parameter selected by CC 101/100, if any.
+    rpn: (u8, u8),
+    /// `(tick, semitones)` each time the bend changed.
+    history: Vec<(u64, f32)>,
+}
+
+impl Default for ChannelBend {
+    fn default() -> Self {
+        Self {
+            position: 0.0,
+            range: 2.0, // General MIDI default
+            rpn: (127, 127),
+            history: Vec::new(),
+        }
+    }
+}
+
+impl ChannelBend {
+    const PITCH_BEND_SENSITIVITY: (u8, u8) = (0, 0);
+
+    fn record(&mut self, tick: u64) {
+        self.history.push((tick, self.position * self.range));
+    }
+
+    /// Bends in effect during `start..end`: the one sounding at `start`, then later changes.
+    fn during(&self, start: u64, end: u64) -> &[(u64, f32)] {
+        let from = self.history.partition_point(|&(t, _)| t <= start);
+        let to = self.history.partition_point(|&(t, _)| t < end);
+        &self.history[from.saturating_sub(1)..to]
+    }
+
+    fn controller(&mut self, tick: u64, controller: u8, value: u8) {
+        match controller {
+            101 => self.rpn.0 = value,
+            100 => self.rpn.1 = value,
+            // Data entry coarse sets whole semitones, fine sets cents
+            6 if self.rpn == Self::PITCH_BEND_SENSITIVITY => {
+                self.range = value as f32 + self.range.fract();
+                self.record(tick);
+            }
+            38 if self.rpn == Self::PITCH_BEND_SENSITIVITY => {
+                self.range = self.range.trunc() + value.min(99) as f32 / 100.0;
+                self.record(tick);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Pedal state of one channel, and the released notes the pedals keep sounding.
+#[derive(Default)]
+struct Pedals {
+    sustain: bool,
+    sostenuto: bool,
+    /// `(tick, down)` each time the soft pedal moved.
+    soft: Vec<(u64, bool)>,
+    /// Keys the sostenuto pedal caught when it went down.
+    caught: HashSet<u8>,
+    /// `(key, start tick)` of notes whose key is up but whose damper a pedal holds off.
+    held: Vec<(u8, u64)>,
+}
+
+impl Pedals {
+    fn holds(&self, key: u8) -> bool {
+        self.sustain || (self.sostenuto && self.caught.contains(&key))
+    }
+
+    fn soft_at(&self, tick: u64) -> bool {
+        let index = self.soft.partition_point(|&(t, _)| t <= tick);
+        index > 0 && self.soft[index - 1].1
+    }
+
+    /// Notes no longer held after a pedal lifted, removed from `held`.
+    fn released(&mut self) -> Vec<(u8, u64)> {
+        let (held, released) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|&(key, _)| self.holds(key));
+        self.held = held;
+        released
+    }
+
+    /// Held notes of `key`, which end when it is struck again.
+    fn restruck(&mut self, key: u8) -> Vec<(u8, u64)> {
+        let (released, held) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|&(k, _)| k == key);
+        self.held = held;
+        released
+    }
+}
+
+/// How raw ticks are converted to time before any tempo is applied.
+#[derive(Clone, Copy)]
+enum TickBase {
+    Metrical { ticks_per_beat: f64 },
+    Timecode { ticks_per_second: f64 },
+}
+
+/// A tempo event, with the absolute position it falls on in every unit.
+struct TempoChange {
+    tick: u64,
+    seconds: f64,
+    beats: f64,
+    micros_per_beat: f64,
+}
+
+/// Tempo map gathered from the tracks of a sequence, used to turn ticks into beats and
+/// seconds.
+struct TempoMap {
+    base: TickBase,
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    const DEFAULT_MICROS_PER_BEAT: f64 = 500_000.0; // 120 BPM
+
+    /// One tempo map per track of the file, most of them shared. Tempo events may live in
+    /// any track of a format 0 or 1 file and apply to all of them (format 1 puts them in
+    /// track 0, but not always), while each track of a format 2 file is a sequence of its
+    /// own with its own tempo.
+    fn for_tracks(smf: &Smf) -> Result<Vec<Rc<Self>>, LoadError> {
+        let base = Self::tick_base(smf.header.timing)?;
+        Ok(if smf.header.format == midly::Format::Sequential {
+            smf.tracks
+                .iter()
+                .map(|track| Rc::new(Self::new(base, std::slice::from_ref(track))))
+                .collect()
+        } else {
+            let shared = Rc::new(Self::new(base, &smf.tracks));
+            vec![shared; smf.tracks.len()]
+        })
+    }
+
+    fn tick_base(timing: midly::Timing) -> Result<TickBase, LoadError> {
+        Ok(match timing {
+            midly::Timing::Metrical(t) if t.as_int() == 0 => {
+                return Err(LoadError::UnsupportedTiming("zero ticks per beat"));
+            }
+            midly::Timing::Metrical(t) => TickBase::Metrical {
+                ticks_per_beat: t.as_int() as f64,
+            },
+            midly::Timing::Timecode(_, 0) => {
+                return Err(LoadError::UnsupportedTiming(
+                    "zero SMPTE subframes per frame",
+                ));
+            }
+            midly::Timing::Timecode(fps, subframes) => TickBase::Timecode {
+                ticks_per_second: fps.as_f32() as f64 * subframes as f64,
+            },
+        })
+    }
+
+    fn new(base: TickBase, tracks: &[midly::Track]) -> Self {
+        let mut raw: Vec<(u64, f64)> = Vec::new();
+        for track in tracks {
+            let mut ticks = 0u64;
+            for event in track {
+                ticks += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(t)) = event.kind {
+                    raw.push((ticks, t.as_int().max(1) as f64));
+                }
+            }
+        }
+        // Stable sort keeps the file order of events that share a tick; the last one wins
+        raw.sort_by_key(|&(tick, _)| tick);
+
+        let mut changes = vec![TempoChange {
+            tick: 0,
+            seconds: 0.0,
+            beats: 0.0,
+            micros_per_beat: Self::DEFAULT_MICROS_PER_BEAT,
+        }];
+        for (tick, micros_per_beat) in raw {
+            let last = changes.last().unwrap();
+            let (seconds, beats) = Self::advance(base, last, tick);
+            if tick == last.tick {
+                // Several tempo events on one tick: only the last one is ever in effect
+                changes.pop();
+            }
+            changes.push(TempoChange {
+                tick,
+                seconds,
+                beats,
+                micros_per_beat,
+            });
+        }
+
+        Self { base, changes }
+    }
+
+    /// Position of `tick` measured from `from`, assuming no tempo change in between.
+    fn advance(base: TickBase, from: &TempoChange, tick: u64) -> (f64, f64) {
+        let delta = tick.saturating_sub(from.tick) as f64;
+        match base {
+            TickBase::Metrical { ticks_per_beat } => {
+                let beats = delta / ticks_per_beat;
+                (
+                    from.seconds + beats * from.micros_per_beat / 1_000_000.0,
+                    from.beats + beats,
+                )
+            }
+            TickBase::Timecode { ticks_per_second } => {
+                // SMPTE timing is absolute; tempo only tells us where the beats fall
+                let seconds = delta / ticks_per_second;
+                (
+                    from.seconds + seconds,
+                    from.beats + seconds * 1_000_000.0 / from.micros_per_beat,
+                )
+            }
+        }
+    }
+
+    fn change_at(&self, tick: u64) -> &TempoChange {
+        let idx = self.changes.partition_point(|c| c.tick <= tick);
+        &self.changes[idx.saturating_sub(1)]
+    }
+
+    /// Returns `(seconds, beats)` for an absolute tick position.
+    fn position(&self, tick: u64) -> (f64, f64) {
+        Self::advance(self.base, self.change_at(tick), tick)
+    }
+
+    /// Tempo at the start of the file, in beats per minute.
+    fn initial_bpm(&self) -> f32 {
+        let micros = self
+            .changes
+            .iter()
+            .find(|c| c.tick == 0)
+            .map_or(Self::DEFAULT_MICROS_PER_BEAT, |c| c.micros_per_beat);
+        (60_000_000.0 / micros) as f32
+    }
+
+    fn has_tempo_changes(&self) -> bool {
+        self.changes.len() > 1
+    }
+}
+
+pub fn load_midi(path: &Path, options: &LoadOptions) -> Result<Song, LoadError> {
+    let data = std::fs::read(path)?;
+    parse_midi(&data, options)
+}
+
+/// Parses an in-memory Standard MIDI File. Tracks without any notes are left out, and a
+/// file with no notes at all is an error.
+pub fn parse_midi(data: &[u8], options: &LoadOptions) -> Result<Song, LoadError> {
+    let smf = Smf::parse(data)?;
+
+    let tempo_maps = TempoMap::for_tracks(&smf)?;
+
+    let mut parsed_tracks = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, track) in smf.tracks.into_iter().enumerate() {
+        let tempo_map = &tempo_maps[i];
+        let mut notes = Vec::new();
+        let mut current_ticks = 0u64;
+        // Start ticks of sounding notes per (channel, key). A NoteOff ends the oldest one, so
+        // a key struck again before it is released gives two overlapping notes, not one.
+        let mut active_notes: HashMap<(u8, u8), VecDeque<u64>> = HashMap::new();
+        let mut track_name = format!("Track {}", i);
+        let mut unmatched_offs = 0usize;
+        let mut programs: HashMap<u8, u8> = HashMap::new(); // First program per channel
+
+        // Bend is per channel, so the bends of a note are read from its channel's history
+        let mut bends: HashMap<u8, ChannelBend> = HashMap::new();
+        let mut pedals: HashMap<u8, Pedals> = HashMap::new();
+
+        let note = |key: u8, start: u64, end: u64, bend: Option<&ChannelBend>, soft: bool| {
+            let (start_sec, start_beat) = tempo_map.position(start);
+            let (end_sec, end_beat) = tempo_map.position(end);
+            let history = bend.map_or(&[][..], |b| b.during(start, end));
+            let bend = if history.iter().all(|&(_, semitones)| semitones == 0.0) {
+                Vec::new()
+            } else {
+                history
+                    .iter()
+                    .map(|&(tick, semitones)| {
+                        let (seconds, beat) = tempo_map.position(tick.max(start));
+                        BendPoint {
+                            beat: beat as f32,
+                            seconds: seconds as f32,
+                            semitones,
+                        }
+                    })
+                    .collect()
+            };
+            MidiNote {
+                pitch: key,
+                start_time: start_beat as f32,
+                duration: (end_beat - start_beat) as f32,
+                start_seconds: start_sec as f32,
+                duration_seconds: (end_sec - start_sec) as f32,
+                bend,
+                soft,
+            }
+        };
+
+        for event in track {
+            current_ticks += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                    if let Ok(s) = std::str::from_utf8(name) {
+                        track_name = s.to_string();
+                    }
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    let channel = channel.as_int();
+                    match message {
+                        midly::MidiMessage::ProgramChange { program } => {
+                            programs.entry(channel).or_insert(program.as_int());
+                        }
+                        midly::MidiMessage::PitchBend { bend } => {
+                            let state = bends.entry(channel).or_default();
+                            state.position = bend.as_int() as f32 / 8192.0;
+                            state.record(current_ticks);
+                        }
+                        midly::MidiMessage::Controller { controller, value } => {
+                            let (controller, value) = (controller.as_int(), value.as_int());
+                            bends.entry(channel).or_default().controller(
+                                current_ticks,
+                                controller,
+                                value,
+                            );
+                            let state = pedals.entry(channel).or_default();
+                            let down = value >= 64;
+                            match controller {
+                                64 => state.sustain = down,
+                                66 if down && !state.sostenuto => {
+                                    // Sostenuto catches the keys sounding as it goes down
+                                    state.sostenuto = true;
+                                    state.caught = active_notes
+                                        .iter()
+                                        .filter(|((c, _), starts)| {
+                                            *c == channel && !starts.is_empty()
+                                        })
+                                        .map(|(&(_, key), _)| key)
+                                        .chain(state.held.iter().map(|&(key, _)| key))
+                                        .collect();
+                                }
+                                66 if !down => {
+                                    state.sostenuto = false;
+                                    state.caught.clear();
+                                }
+                                67 => state.soft.push((current_ticks, down)),
+                                _ => {}
+                            }
+                            for (key, start) in state.released() {
+                                let soft = state.soft_at(start);
+                                let bend = bends.get(&channel);
+                                notes.push((channel, note(key, start, current_ticks, bend, soft)));
+                            }
+                        }
+                        midly::MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            let key = key.as_int();
+                            if let Some(state) = pedals.get_mut(&channel) {
+                                for (key, start) in state.restruck(key) {
+                                    let soft = state.soft_at(start);
+                                    let bend = bends.get(&channel);
+                                    notes.push((
+                                        channel,
+                                        note(key, start, current_ticks, bend, soft),
+                                    ));
+                                }
+                            }
+                            active_notes
+                                .entry((channel, key))
+                                .or_default()
+                                .push_back(current_ticks);
+                        }
+                        midly::MidiMessage::NoteOn { key, .. }
+                        | midly::MidiMessage::NoteOff { key, .. } => {
+                            let key = key.as_int();
+                            let state = pedals.entry(channel).or_default();
+                            match active_notes
+                                .get_mut(&(channel, key))
+                                .and_then(|starts| starts.pop_front())
+                            {
+                                Some(start) if options.sustain_pedal && state.holds(key) => {
+                                    state.held.push((key, start));
+                                }
+                                Some(start) => {
+                                    let soft = state.soft_at(start);
+                                    let bend = bends.get(&channel);
+                                    notes.push((
+                                        channel,
+                                        note(key, start, current_ticks, bend, soft),
+                                    ));
+                                }
+                                None => unmatched_offs += 1,
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Notes never released end with the track rather than vanishing. Notes a pedal still
+        // holds are ended too, but were released as the file intended.
+        let soft_at =
+            |channel: u8, start: u64| pedals.get(&channel).is_some_and(|p| p.soft_at(start));
+        let mut dangling = 0usize;
+        for (&(channel, key), starts) in &active_notes {
+            for &start in starts {
+                let (bend, soft) = (bends.get(&channel), soft_at(channel, start));
+                notes.push((channel, note(key, start, current_ticks, bend, soft)));
+                dangling += 1;
+            }
+        }
+        for (&channel, state) in &pedals {
+            for &(key, start) in &state.held {
+                let (bend, soft) = (bends.get(&channel), soft_at(channel, start));
+                notes.push((channel, note(key, start, current_ticks, bend, soft)));
+            }
+        }
+        if unmatched_offs > 0 {
+            warnings.push(format!(
+                "{track_name}: {unmatched_offs} NoteOff events without a sounding note"
+            ));
+        }
+        if dangling > 0 {
+            warnings.push(format!(
+                "{track_name}: {dangling} notes still on at the end of the track were closed there"
+            ));
+        }
+
+        let mut channels: Vec<u8> = notes.iter().map(|&(channel, _)| channel).collect();
+        channels.sort_unstable();
+        channels.dedup();
+        if options.split_channels && channels.len() > 1 {
+            for channel in channels {
+                if channel == gm::PERCUSSION_CHANNEL && !options.include_percussion {
+                    continue;
+                }
+                // A channel that never changes program plays program 0
+                let instrument = if channel == gm::PERCUSSION_CHANNEL {
+                    "Percussion"
+                } else {
+                    gm::instrument_name(programs.get(&channel).copied().unwrap_or(0))
+                };
+                let name = format!("{track_name} · Ch {}: {instrument}", channel + 1);
+                let channel_notes = notes
+                    .iter()
+                    .filter(|&&(c, _)| c == channel)
+                    .map(|(_, n)| n.clone())
+                    .collect();
+                parsed_tracks.push(TrackData::new(name, channel_notes));
+            }
+        } else if !notes.is_empty() {
+            let notes = notes.into_iter().map(|(_, n)| n).collect();
+            parsed_tracks.push(TrackData::new(track_name, notes));
+        }
+    }
+    if parsed_tracks.is_empty() {
+        return Err(LoadError::NoNoteTracks);
+    }
+    Ok(Song {
+        tracks: parsed_tracks,
+        initial_bpm: tempo_maps[0].initial_bpm(),
+        has_tempo_changes: tempo_maps.iter().any(|map| map.has_tempo_changes()),
+        warnings,
+    })
+}
+
+/// Note name with octave, e.g. `C4` for MIDI note 60.
+pub fn midi_pitch_to_name(pitch: u8) -> String {
+    const NOTE_NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    let octave = (pitch as i16 / 12) - 1;
+    let note_index = (pitch % 12) as usize;
+    format!("{}{}", NOTE_NAMES[note_index], octave)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKS_PER_BEAT: u16 = 96;
+
+    /// Variable-length quantity, as used for delta times.
+    fn vlq(mut value: u32) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.insert(0, (value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes
+    }
+
+    /// One track chunk from `(delta, event bytes)` pairs, with End of Track appended.
+    fn track(events: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (delta, bytes) in events {
+            body.extend(vlq(*delta));
+            body.extend_from_slice(bytes);
+        }
+        body.extend([0x00, 0xff, 0x2f, 0x00]);
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend((body.len() as u32).to_be_bytes());
+        chunk.extend(body);
+        chunk
+    }
+
+    fn smf(format: u16, division: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"MThd".to_vec();
+        data.extend(6u32.to_be_bytes());
+        data.extend(format.to_be_bytes());
+        data.extend((tracks.len() as u16).to_be_bytes());
+        data.extend(division.to_be_bytes());
+        for t in tracks {
+            data.extend(t);
+        }
+        data
+    }
+
+    fn parse(data: &[u8]) -> Result<Song, LoadError> {
+        parse_midi(data, &LoadOptions::default())
+    }
+
+    fn notes_of(song: &Song) -> Vec<(u8, f32, f32)> {
+        song.tracks[0]
+            .notes
+            .iter()
+            .map(|n| (n.pitch, n.start_time, n.duration))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_key_closes_oldest_note_first() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0x90, 60, 100]),
+                (96, &[0x90, 60, 100]),
+                (96, &[0x80, 60, 0]),
+                (96, &[0x80, 60, 0]),
+            ])],
+        );
+        let song = parse(&data).unwrap();
+        assert_eq!(notes_of(&song), vec![(60, 0.0, 2.0), (60, 1.0, 2.0)]);
+        assert!(song.warnings.is_empty());
+    }
+
+    #[test]
+    fn same_key_on_different_channels_does_not_collide() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0x90, 64, 100]),
+                (48, &[0x91, 64, 100]),
+                (48, &[0x80, 64, 0]),
+                (96, &[0x81, 64, 0]),
+            ])],
+        );
+        let song = parse(&data).unwrap();
+        assert_eq!(notes_of(&song), vec![(64, 0.0, 1.0), (64, 0.5, 1.5)]);
+    }
+
+    #[test]
+    fn note_on_with_zero_velocity_releases() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[(0, &[0x90, 67, 100]), (192, &[0x90, 67, 0])])],
+        );
+        let song = parse(&data).unwrap();
+        assert_eq!(notes_of(&song), vec![(67, 0.0, 2.0)]);
+    }
+
+    #[test]
+    fn dangling_note_is_closed_at_track_end() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0x90, 60, 100]),
+                (96, &[0x90, 62, 100]),
+                (96, &[0x80, 62, 0]),
+                (192, &[0xff, 0x01, 0x00]), // Empty text event pushes the track end out
+            ])],
+        );
+        let song = parse(&data).unwrap();
+        assert_eq!(notes_of(&song), vec![(60, 0.0, 4.0), (62, 1.0, 1.0)]);
+        assert_eq!(song.warnings.len(), 1);
+        assert!(song.warnings[0].contains("1 notes still on"));
+    }
+
+    #[test]
+    fn unmatched_note_off_is_reported() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0x80, 50, 0]),
+                (0, &[0x90, 60, 100]),
+                (96, &[0x80, 60, 0]),
+            ])],
+        );
+        let song = parse(&data).unwrap();
+        assert_eq!(notes_of(&song), vec![(60, 0.0, 1.0)]);
+        assert!(song.warnings[0].contains("1 NoteOff"));
+    }
+
+    #[test]
+    fn tempo_map_converts_beats_to_seconds() {
+        let conductor = track(&[
+            (0, &[0xff, 0x51, 0x03, 0x07, 0xa1, 0x20]), // 500 000 us per beat
+            (192, &[0xff, 0x51, 0x03, 0x0f, 0x42, 0x40]), // 1 000 000 us per beat
+        ]);
+        let melody = track(&[(96, &[0x90, 72, 90]), (192, &[0x80, 72, 0])]);
+        let song = parse(&smf(1, TICKS_PER_BEAT, &[conductor, melody])).unwrap();
+        let note = &song.tracks[0].notes[0];
+        assert_eq!((note.start_time, note.duration), (1.0, 2.0));
+        // Half a second to its start, then one beat at each tempo
+        assert!((note.start_seconds - 0.5).abs() < 1e-6);
+        assert!((note.duration_seconds - 1.5).abs() < 1e-6);
+        assert_eq!(song.initial_bpm, 120.0);
+        assert!(song.has_tempo_changes);
+    }
+
+    #[test]
+    fn format_2_sequences_keep_their_own_tempo() {
+        let slow = track(&[
+            (0, &[0xff, 0x51, 0x03, 0x0f, 0x42, 0x40]), // 1 000 000 us per beat
+            (0, &[0x90, 60, 100]),
+            (96, &[0x80, 60, 0]),
+        ]);
+        let fast = track(&[(0, &[0x90, 62, 100]), (96, &[0x80, 62, 0])]);
+        let song = parse(&smf(2, TICKS_PER_BEAT, &[slow, fast])).unwrap();
+        let seconds: Vec<f32> = song
+            .tracks
+            .iter()
+            .map(|t| t.notes[0].duration_seconds)
+            .collect();
+        assert_eq!(seconds, [1.0, 0.5]);
+        assert_eq!(song.initial_bpm, 60.0);
+    }
+
+    #[test]
+    fn pitch_bend_follows_rpn_range() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0xb0, 101, 0]), // Select RPN 0, pitch bend sensitivity
+                (0, &[0xb0, 100, 0]),
+                (0, &[0xb0, 6, 12]),      // +-12 semitones
+                (0, &[0xe0, 0x00, 0x60]), // Half way up
+                (0, &[0x90, 60, 100]),
+                (48, &[0xe0, 0x00, 0x40]), // Centre
+                (48, &[0x80, 60, 0]),
+                (0, &[0x90, 62, 100]),
+                (96, &[0x80, 62, 0]),
+            ])],
+        );
+        let song = parse(&data).unwrap();
+        let bends: Vec<Vec<(f32, f32)>> = song.tracks[0]
+            .notes
+            .iter()
+            .map(|n| n.bend.iter().map(|b| (b.beat, b.semitones)).collect())
+            .collect();
+        assert_eq!(bends, [vec![(0.0, 6.0), (0.5, 0.0)], vec![]]);
+    }
+
+    #[test]
+    fn pedals_hold_released_notes() {
+        let sustain = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0xb0, 64, 127]),
+                (0, &[0x90, 60, 100]),
+                (48, &[0x80, 60, 0]),
+                (0, &[0x90, 64, 100]),
+                (48, &[0x80, 64, 0]),
+                (48, &[0xb0, 67, 127]), // Soft pedal
+                (0, &[0x90, 60, 100]),  // Struck again: the held C ends here
+                (24, &[0x80, 60, 0]),
+                (24, &[0xb0, 64, 0]),
+            ])],
+        );
+        let options = LoadOptions {
+            sustain_pedal: true,
+            ..LoadOptions::default()
+        };
+        let song = parse_midi(&sustain, &options).unwrap();
+        assert_eq!(
+            notes_of(&song),
+            [(60, 0.0, 1.5), (64, 0.5, 1.5), (60, 1.5, 0.5)]
+        );
+        let soft: Vec<bool> = song.tracks[0].notes.iter().map(|n| n.soft).collect();
+        assert_eq!(soft, [false, false, true]);
+        assert_eq!(
+            notes_of(&parse(&sustain).unwrap()),
+            [(60, 0.0, 0.5), (64, 0.5, 0.5), (60, 1.5, 0.25)]
+        );
+
+        // Sostenuto holds only the keys down when it was pressed
+        let sostenuto = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0x90, 48, 100]),
+                (24, &[0xb0, 66, 127]),
+                (24, &[0x80, 48, 0]),
+                (0, &[0x90, 72, 100]),
+                (48, &[0x80, 72, 0]),
+                (96, &[0xb0, 66, 0]),
+            ])],
+        );
+        assert_eq!(
+            notes_of(&parse_midi(&sostenuto, &options).unwrap()),
+            [(48, 0.0, 2.0), (72, 0.5, 0.5)]
+        );
+    }
+
+    #[test]
+    fn multi_channel_track_splits_into_named_tracks() {
+        let data = smf(
+            0,
+            TICKS_PER_BEAT,
+            &[track(&[
+                (0, &[0xc0, 40]), // Violin on channel 1
+                (0, &[0x90, 76, 100]),
+                (0, &[0x91, 40, 100]),
+                (0, &[0x99, 36, 100]),
+                (96, &[0x80, 76, 0]),
+                (0, &[0x81, 40, 0]),
+                (0, &[0x89, 36, 0]),
+            ])],
+        );
+        assert_eq!(parse(&data).unwrap().tracks.len(), 1);
+
+        let mut options = LoadOptions {
+            split_channels: true,
+            ..LoadOptions::default()
+        };
+        let names = |options: &LoadOptions| -> Vec<String> {
+            let song = parse_midi(&data, options).unwrap();
+            song.tracks.into_iter().map(|t| t.name).collect()
+        };
+        assert_eq!(
+            names(&options),
+            [
+                "Track 0 · Ch 1: Violin",
+                "Track 0 · Ch 2: Acoustic Grand Piano"
+            ]
+        );
+        options.include_percussion = true;
+        assert_eq!(names(&options)[2], "Track 0 · Ch 10: Percussion");
+    }
+
+    #[test]
+    fn unusable_files_are_typed_errors() {
+        let empty = smf(0, TICKS_PER_BEAT, &[track(&[])]);
+        assert!(matches!(parse(&empty), Err(LoadError::NoNoteTracks)));
+
+        let note = track(&[(0, &[0x90, 60, 100]), (96, &[0x80, 60, 0])]);
+        let zero_division = smf(0, 0, std::slice::from_ref(&note));
+        assert!(matches!(
+            parse(&zero_division),
+            Err(LoadError::UnsupportedTiming(_))
+        ));
+
+        assert!(matches!(
+            parse(b"not a midi file"),
+            Err(LoadError::Parse(_))
+        ));
+    }
+}
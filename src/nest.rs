@@ -0,0 +1,444 @@
+//! Nesting comb strips onto sheets of stock for cutting in one go.
+//!
+//! Every row of every piece is a separate part. Parts are packed in shelves: sorted tallest
+//! first, each is put at the end of the first shelf with room for it, and a new shelf is
+//! opened under the last one (or on a new sheet) when none has. Combs are long and all about
+//! the same height, which is where shelf packing does well. Parts are never rotated, so the
+//! piece labels and pitch marks stay readable.
+
+use crate::comb::CombSettings;
+use crate::geometry::Point;
+use crate::midi::MidiNote;
+use crate::strip::StripError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NestSettings {
+    pub sheet_width: f32,  // mm
+    pub sheet_height: f32, // mm
+    /// Stock left between parts and around the sheet edge, in mm.
+    pub spacing: f32,
+    /// Give each selected track a comb of its own instead of merging them into one.
+    pub per_track: bool,
+}
+
+impl Default for NestSettings {
+    fn default() -> Self {
+        Self {
+            sheet_width: 600.0,
+            sheet_height: 300.0,
+            spacing: 3.0,
+            per_track: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NestError {
+    NothingToNest,
+    InvalidSheet,
+    PartTooLarge {
+        label: String,
+        width: f32,
+        height: f32,
+    },
+    Strips(StripError),
+}
+
+impl fmt::Display for NestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NestError::NothingToNest => {
+                write!(f, "Nothing to nest: no notes in the selected tracks.")
+            }
+            NestError::InvalidSheet => write!(
+                f,
+                "The sheet has no room inside its spacing. Use a larger sheet or less spacing."
+            ),
+            NestError::PartTooLarge {
+                label,
+                width,
+                height,
+            } => write!(
+                f,
+                "Strip {label} is {width:.1} × {height:.1} mm, larger than the usable sheet. \
+                 Set a shorter maximum strip length or use a larger sheet."
+            ),
+            NestError::Strips(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for NestError {}
+
+impl From<StripError> for NestError {
+    fn from(e: StripError) -> Self {
+        NestError::Strips(e)
+    }
+}
+
+/// One comb row of one piece, in place on its sheet.
+#[derive(Clone, Debug)]
+pub struct NestedPart {
+    pub sheet: usize,
+    /// Closed outline in sheet coordinates, before kerf compensation.
+    pub outline: Vec<Point>,
+    /// Piece labels to engrave, with their text starting at the point.
+    pub labels: Vec<(Point, String)>,
+    /// Where each note starts on the spine, for engraving, with its pitch.
+    pub notes: Vec<(Point, u8)>,
+    /// Material the part takes, in mm².
+    pub area: f32,
+}
+
+/// Parts packed onto as many sheets as they need.
+#[derive(Clone, Debug)]
+pub struct Nesting {
+    pub sheet_width: f32,
+    pub sheet_height: f32,
+    pub sheets: usize,
+    pub parts: Vec<NestedPart>,
+    /// Depth of stock taken on each sheet, from its top edge to below the lowest shelf.
+    pub used_height: Vec<f32>,
+}
+
+impl Nesting {
+    /// Space between sheets when they are exported together, in mm.
+    pub const SHEET_GAP: f32 = 20.0;
+
+    /// Where the top-left corner of a sheet sits in an export of every sheet.
+    pub fn sheet_origin(&self, sheet: usize) -> Point {
+        (0.0, sheet as f32 * (self.sheet_height + Self::SHEET_GAP))
+    }
+
+    /// Width and height of an export of every sheet, stacked top to bottom.
+    pub fn export_size(&self) -> (f32, f32) {
+        let count = self.sheets as f32;
+        (
+            self.sheet_width,
+            count * self.sheet_height + (count - 1.0) * Self::SHEET_GAP,
+        )
+    }
+
+    /// How much of each sheet the parts use, one line per sheet and a total.
+    pub fn report(&self) -> String {
+        let sheet_area = self.sheet_width * self.sheet_height;
+        let mut lines = vec![format!(
+            "{} on {} of {:.0} × {:.0} mm",
+            count(self.parts.len(), "strip"),
+            count(self.sheets, "sheet"),
+            self.sheet_width,
+            self.sheet_height
+        )];
+        for sheet in 0..self.sheets {
+            let parts: Vec<&NestedPart> = self.parts.iter().filter(|p| p.sheet == sheet).collect();
+            let area: f32 = parts.iter().map(|p| p.area).sum();
+            lines.push(format!(
+                "Sheet {}: {}, {:.1}% utilization, {:.0} mm of {:.0} mm depth used",
+                sheet + 1,
+                count(parts.len(), "strip"),
+                100.0 * area / sheet_area,
+                self.used_height[sheet],
+                self.sheet_height
+            ));
+        }
+        let total: f32 = self.parts.iter().map(|p| p.area).sum();
+        lines.push(format!(
+            "Overall: {:.1}% utilization",
+            100.0 * total / (sheet_area * self.sheets as f32)
+        ));
+        lines.join("\n")
+    }
+}
+
+/// Shelf-packs boxes of the given width and height onto sheets. Returns each box's sheet
+/// and top-left corner, or the index of the first box that fits on no sheet.
+pub fn shelf_pack(
+    sizes: &[(f32, f32)],
+    settings: &NestSettings,
+) -> Result<Vec<(usize, Point)>, usize> {
+    struct Shelf {
+        sheet: usize,
+        y: f32,
+        height: f32,
+        next_x: f32,
+    }
+
+    let spacing = settings.spacing.max(0.0);
+    let (right, bottom) = (
+        settings.sheet_width - spacing,
+        settings.sheet_height - spacing,
+    );
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b].1.total_cmp(&sizes[a].1));
+
+    let mut shelves: Vec<Shelf> = Vec::new();
+    let mut placed = vec![(0, (0.0, 0.0)); sizes.len()];
+    for index in order {
+        let (width, height) = sizes[index];
+        if spacing + width > right || spacing + height > bottom {
+            return Err(index);
+        }
+        let shelf = match shelves
+            .iter()
+            .position(|s| s.next_x + width <= right && height <= s.height)
+        {
+            Some(shelf) => shelf,
+            None => {
+                // Under the last shelf if it fits on that sheet, else on a fresh sheet
+                let (sheet, y) = shelves.last().map_or((0, spacing), |last| {
+                    let y = last.y + last.height + spacing;
+                    if y + height <= bottom {
+                        (last.sheet, y)
+                    } else {
+                        (last.sheet + 1, spacing)
+                    }
+                });
+                shelves.push(Shelf {
+                    sheet,
+                    y,
+                    height,
+                    next_x: spacing,
+                });
+                shelves.len() - 1
+            }
+        };
+        let shelf = &mut shelves[shelf];
+        placed[index] = (shelf.sheet, (shelf.next_x, shelf.y));
+        shelf.next_x += width + spacing;
+    }
+    Ok(placed)
+}
+
+fn count(n: usize, noun: &str) -> String {
+    format!("{n} {noun}{}", if n == 1 { "" } else { "s" })
+}
+
+/// Spreadsheet-style tag for the comb at `index`: A to Z, then AA, AB and so on.
+fn comb_tag(mut index: usize) -> String {
+    let mut tag = Vec::new();
+    loop {
+        tag.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    tag.reverse();
+    String::from_utf8(tag).unwrap_or_default()
+}
+
+fn polygon_area(points: &[Point]) -> f32 {
+    let n = points.len();
+    let twice: f32 = (0..n)
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum();
+    twice.abs() / 2.0
+}
+
+impl CombSettings {
+    /// Lays out a comb for each of `combs`, splits it into strips as usual and packs every
+    /// row of every strip onto sheets. With several combs, labels are prefixed A, B, C…
+    pub fn nest_combs(&self, combs: &[Vec<MidiNote>]) -> Result<Nesting, NestError> {
+        let settings = &self.nest_settings;
+        if settings.sheet_width <= 2.0 * settings.spacing
+            || settings.sheet_height <= 2.0 * settings.spacing
+        {
+            return Err(NestError::InvalidSheet);
+        }
+
+        // Tagged by their place in `combs`, so a comb keeps its letter when one before it
+        // is empty
+        let tagged = combs.len() > 1;
+        let mut parts = Vec::new();
+        for (comb, notes) in combs.iter().enumerate() {
+            let Some(layout) = self.comb_layout(notes) else {
+                continue;
+            };
+            let strips = self.comb_strips(&layout)?;
+            for piece in &strips.pieces {
+                for (lane_index, lane) in layout.lanes.iter().enumerate() {
+                    let outline = piece.outlines[lane_index].clone();
+                    let mut labels: Vec<(Point, String)> =
+                        piece.labels.get(lane_index).cloned().into_iter().collect();
+                    if tagged {
+                        let tag = comb_tag(comb);
+                        match labels.first_mut() {
+                            Some((_, text)) => *text = format!("{tag}{text}"),
+                            None => labels.push((
+                                piece.place(self.spine_label_at(lane, piece.start + 1.0)),
+                                tag,
+                            )),
+                        }
+                    }
+                    let notes = lane
+                        .segments
+                        .iter()
+                        .filter(|s| s.0 >= piece.start && s.0 < piece.end)
+                        .map(|&(start, _, pitch)| (piece.place((start, lane.base)), pitch))
+                        .collect();
+                    parts.push(NestedPart {
+                        sheet: 0,
+                        area: polygon_area(&outline),
+                        outline,
+                        labels,
+                        notes,
+                    });
+                }
+            }
+        }
+        if parts.is_empty() {
+            return Err(NestError::NothingToNest);
+        }
+
+        // Pack by the cut path, so the spacing is left between kerfs
+        let bounds: Vec<(Point, Point)> = parts
+            .iter()
+            .map(|part| {
+                let cut = self.cut_outline(&part.outline);
+                cut.iter().fold(
+                    ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+                    |(min, max), &(x, y)| {
+                        ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+                    },
+                )
+            })
+            .collect();
+        let sizes: Vec<(f32, f32)> = bounds
+            .iter()
+            .map(|(min, max)| (max.0 - min.0, max.1 - min.1))
+            .collect();
+        let placed = shelf_pack(&sizes, settings).map_err(|index| {
+            let label = parts[index]
+                .labels
+                .first()
+                .map_or_else(|| (index + 1).to_string(), |(_, text)| text.clone());
+            NestError::PartTooLarge {
+                label,
+                width: sizes[index].0,
+                height: sizes[index].1,
+            }
+        })?;
+
+        let sheets = placed
+            .iter()
+            .map(|&(sheet, _)| sheet + 1)
+            .max()
+            .unwrap_or(1);
+        let mut used_height = vec![0.0f32; sheets];
+        for (i, part) in parts.iter_mut().enumerate() {
+            let (sheet, (x, y)) = placed[i];
+            let (min, _) = bounds[i];
+            let shift = |(px, py): Point| (px + x - min.0, py + y - min.1);
+            part.sheet = sheet;
+            part.outline = part.outline.iter().copied().map(shift).collect();
+            for (at, _) in &mut part.labels {
+                *at = shift(*at);
+            }
+            for (at, _) in &mut part.notes {
+                *at = shift(*at);
+            }
+            used_height[sheet] = used_height[sheet].max(y + sizes[i].1 + settings.spacing);
+        }
+        Ok(Nesting {
+            sheet_width: settings.sheet_width,
+            sheet_height: settings.sheet_height,
+            sheets,
+            parts,
+            used_height,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet(sheet_width: f32, sheet_height: f32) -> NestSettings {
+        NestSettings {
+            sheet_width,
+            sheet_height,
+            spacing: 2.0,
+            per_track: true,
+        }
+    }
+
+    fn part(sheet: usize, area: f32) -> NestedPart {
+        NestedPart {
+            sheet,
+            outline: Vec::new(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            area,
+        }
+    }
+
+    #[test]
+    fn parts_share_a_shelf_while_it_has_room() {
+        let placed = shelf_pack(&[(30.0, 10.0), (30.0, 8.0)], &sheet(100.0, 50.0));
+        assert_eq!(placed, Ok(vec![(0, (2.0, 2.0)), (0, (34.0, 2.0))]));
+    }
+
+    #[test]
+    fn a_full_shelf_opens_one_under_it() {
+        let placed = shelf_pack(&[(60.0, 10.0), (60.0, 8.0)], &sheet(100.0, 50.0));
+        assert_eq!(placed, Ok(vec![(0, (2.0, 2.0)), (0, (2.0, 14.0))]));
+    }
+
+    #[test]
+    fn a_full_sheet_opens_another() {
+        let placed = shelf_pack(&[(60.0, 20.0), (60.0, 20.0)], &sheet(100.0, 30.0));
+        assert_eq!(placed, Ok(vec![(0, (2.0, 2.0)), (1, (2.0, 2.0))]));
+    }
+
+    #[test]
+    fn a_part_too_large_is_reported_by_its_own_index() {
+        // Packed first, being the tallest, but still named by where it was given
+        let placed = shelf_pack(&[(10.0, 5.0), (200.0, 8.0)], &sheet(100.0, 50.0));
+        assert_eq!(placed, Err(1));
+    }
+
+    #[test]
+    fn report_gives_utilization_per_sheet_and_overall() {
+        let nesting = Nesting {
+            sheet_width: 100.0,
+            sheet_height: 50.0,
+            sheets: 2,
+            parts: vec![part(0, 600.0), part(0, 400.0), part(1, 500.0)],
+            used_height: vec![30.0, 12.0],
+        };
+        assert_eq!(
+            nesting.report(),
+            "3 strips on 2 sheets of 100 × 50 mm\n\
+             Sheet 1: 2 strips, 20.0% utilization, 30 mm of 50 mm depth used\n\
+             Sheet 2: 1 strip, 10.0% utilization, 12 mm of 50 mm depth used\n\
+             Overall: 15.0% utilization"
+        );
+    }
+
+    #[test]
+    fn combs_are_tagged_by_their_place_even_after_an_empty_one() {
+        let note = MidiNote {
+            pitch: 72,
+            start_time: 0.0,
+            duration: 2.0,
+            start_seconds: 0.0,
+            duration_seconds: 1.0,
+            bend: Vec::new(),
+            soft: false,
+        };
+        let combs = [vec![note.clone()], Vec::new(), vec![note]];
+        let nesting = CombSettings::default().nest_combs(&combs).unwrap();
+        let tags: Vec<&str> = nesting
+            .parts
+            .iter()
+            .map(|p| p.labels[0].1.as_str())
+            .collect();
+        assert_eq!(tags, ["A", "C"]);
+    }
+}
@@ -0,0 +1,713 @@
+//! Printable PDF of the comb at true scale, tiled over sheets of office paper.
+//!
+//! The comb is stored once as a form XObject and every page shows a window onto it, so a
+//! comb several metres long stays a small file. Neighbouring pages overlap by a strip that
+//! carries split alignment targets: trim the later page along its dashed line, lay it over
+//! the grey strip of the earlier one and line the halves of the targets up. A cover page
+//! lists the song and the settings the comb was made with, with a scale bar to check the
+//! printer did not resize anything.
+//!
+//! Drawing is done in millimetres with Y pointing down, like the rest of the geometry; each
+//! page stream starts by flipping PDF's point-based, Y-up space to match.
+
+use crate::geometry::Point;
+use crate::strip::StripError;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PaperSize {
+    A4,
+    Letter,
+}
+
+impl PaperSize {
+    pub const ALL: [PaperSize; 2] = [PaperSize::A4, PaperSize::Letter];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PaperSize::A4 => "A4",
+            PaperSize::Letter => "US Letter",
+        }
+    }
+
+    /// Width and height in portrait orientation, in mm.
+    pub fn size(self) -> (f32, f32) {
+        match self {
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::Letter => (215.9, 279.4),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PdfSettings {
+    pub paper: PaperSize,
+    /// Long edge horizontal, which suits long single-row combs.
+    pub landscape: bool,
+    /// Comb printed on both of two neighbouring pages, in mm.
+    pub overlap: f32,
+}
+
+impl Default for PdfSettings {
+    fn default() -> Self {
+        Self {
+            paper: PaperSize::A4,
+            landscape: true,
+            overlap: 10.0,
+        }
+    }
+}
+
+impl PdfSettings {
+    /// Page width and height in mm, as printed.
+    pub fn page_size(&self) -> (f32, f32) {
+        let (width, height) = self.paper.size();
+        if self.landscape {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+/// What the cover page says about the comb.
+pub struct Cover {
+    pub title: String,
+    /// Label and value pairs, listed in order.
+    pub details: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum PdfError {
+    NothingToDraw,
+    OverlapTooLarge { overlap: f32, page: f32 },
+    Strips(StripError),
+}
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdfError::NothingToDraw => {
+                write!(f, "Nothing to draw: no notes in the selected track.")
+            }
+            PdfError::OverlapTooLarge { overlap, page } => write!(
+                f,
+                "A {overlap:.1} mm overlap leaves nothing new on each page: the printable area \
+                 is only {page:.1} mm across. Use a smaller overlap."
+            ),
+            PdfError::Strips(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PdfError {}
+
+impl From<StripError> for PdfError {
+    fn from(e: StripError) -> Self {
+        PdfError::Strips(e)
+    }
+}
+
+const PT_PER_MM: f32 = 72.0 / 25.4;
+/// Unprinted border on every page, wide enough for most home printers, in mm.
+const PAGE_MARGIN: f32 = 10.0;
+/// Strip at the bottom of each page for its number, in mm.
+const FOOTER: f32 = 7.0;
+/// Blank stock around the comb where the alignment targets sit, in mm.
+const MARK_SPACE: f32 = 8.0;
+const TARGET_RADIUS: f32 = 2.5;
+const OUTLINE_WIDTH: f32 = 0.1;
+const MARK_WIDTH: f32 = 0.15;
+/// Font sizes in mm: 20, 10 and 7 pt.
+const TITLE_SIZE: f32 = 7.0;
+const TEXT_SIZE: f32 = 3.5;
+const SMALL_SIZE: f32 = 2.5;
+/// Length of the scale bar on the cover, in mm.
+const SCALE_BAR: f32 = 100.0;
+
+/// Lays `outlines` (mm, Y down) from a `width` × `height` mm comb out over as many pages as
+/// it takes, behind a cover page.
+///
+/// `bleed` is how far outlines may reach past the comb, from kerf compensation; the tiled
+/// area grows by that much on every side so nothing is clipped.
+pub fn render(
+    outlines: &[Vec<Point>],
+    (width, height): (f32, f32),
+    bleed: f32,
+    cover: &Cover,
+    settings: &PdfSettings,
+) -> Result<Vec<u8>, PdfError> {
+    if outlines.is_empty() {
+        return Err(PdfError::NothingToDraw);
+    }
+    let (page_width, page_height) = settings.page_size();
+    let view = (
+        page_width - 2.0 * PAGE_MARGIN,
+        page_height - 2.0 * PAGE_MARGIN - FOOTER,
+    );
+    let overlap = settings.overlap.max(0.0);
+    let shortest = view.0.min(view.1);
+    if overlap * 2.0 >= shortest {
+        return Err(PdfError::OverlapTooLarge {
+            overlap,
+            page: shortest,
+        });
+    }
+
+    // The tiled sheet is the comb plus room for the targets, with its origin at `-pad`
+    let bleed = bleed.max(0.0);
+    let pad = bleed + MARK_SPACE;
+    let sheet = (width + 2.0 * pad, height + 2.0 * pad);
+    let step = (view.0 - overlap, view.1 - overlap);
+    let count = |length: f32, step: f32| ((length - overlap) / step).ceil().max(1.0) as usize;
+    let (columns, rows) = (count(sheet.0, step.0), count(sheet.1, step.1));
+
+    // Seams run through the middle of each overlap, and every target sits on one
+    let seams = |count: usize, step: f32| -> Vec<f32> {
+        (1..count)
+            .map(|i| -pad + i as f32 * step + overlap / 2.0)
+            .collect()
+    };
+    let (seams_x, seams_y) = (seams(columns, step.0), seams(rows, step.1));
+    let edge = bleed + MARK_SPACE / 2.0;
+    let mut targets = Vec::new();
+    for &x in &seams_x {
+        targets.extend([(x, -edge), (x, height + edge)]);
+        targets.extend(seams_y.iter().map(|&y| (x, y)));
+    }
+    for &y in &seams_y {
+        targets.extend([(-edge, y), (width + edge, y)]);
+    }
+
+    let mut sheet_ops = String::new();
+    let _ = writeln!(sheet_ops, "0 G {OUTLINE_WIDTH} w 1 j");
+    for outline in outlines {
+        path(&mut sheet_ops, outline);
+        sheet_ops.push_str("h S\n");
+    }
+    let _ = writeln!(sheet_ops, "{MARK_WIDTH} w");
+    for &target in &targets {
+        draw_target(&mut sheet_ops, target);
+    }
+
+    let mut document = Document::default();
+    let catalog = document.add("<< /Type /Catalog /Pages 2 0 R >>");
+    let pages = document.add(""); // Filled in once the pages are known
+    let regular = document
+        .add("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>");
+    let bold = document.add(
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>",
+    );
+    let comb = document.stream(
+        &format!(
+            "/Type /XObject /Subtype /Form /BBox [{} {} {} {}]",
+            num(-pad),
+            num(-pad),
+            num(width + pad),
+            num(height + pad)
+        ),
+        &sheet_ops,
+    );
+    let info = document.add(format!(
+        "<< /Title {} /Producer (midi_to_bar) >>",
+        pdf_string(&cover.title)
+    ));
+    let resources = format!(
+        "<< /Font << /F1 {regular} 0 R /F2 {bold} 0 R >> /XObject << /Comb {comb} 0 R >> >>"
+    );
+
+    let total = columns * rows;
+    let page_number = |column: usize, row: usize| row * columns + column + 1;
+    let mut streams = Vec::with_capacity(total + 1);
+
+    // Cover: title, details, instructions, scale bar and a map of the pages
+    let mut ops = page_start(page_height);
+    let mut y = PAGE_MARGIN + TITLE_SIZE;
+    text(&mut ops, "F2", TITLE_SIZE, (PAGE_MARGIN, y), &cover.title);
+    y += TITLE_SIZE;
+    let layout = if rows > 1 {
+        format!("{total} ({rows} rows of {columns})")
+    } else {
+        total.to_string()
+    };
+    let orientation = if settings.landscape {
+        "landscape"
+    } else {
+        "portrait"
+    };
+    let details = cover.details.iter().map(|(l, v)| (l.as_str(), v.clone()));
+    let printing = [
+        ("Comb size", format!("{width:.1} × {height:.1} mm")),
+        (
+            "Paper",
+            format!(
+                "{} {orientation}, {overlap:.0} mm overlap",
+                settings.paper.label()
+            ),
+        ),
+        ("Pages", layout),
+    ];
+    for (label, value) in details.chain(printing) {
+        text(
+            &mut ops,
+            "F2",
+            TEXT_SIZE,
+            (PAGE_MARGIN, y),
+            &format!("{label}:"),
+        );
+        text(&mut ops, "F1", TEXT_SIZE, (PAGE_MARGIN + 45.0, y), &value);
+        y += TEXT_SIZE * 1.5;
+    }
+    y += TEXT_SIZE;
+    for line in [
+        "Print at actual size (100%), without fit-to-page, and check the bar below.",
+        "Trim each page along its dashed line, lay it over the grey strip of the page before",
+        "it so the halves of every target meet, and tape it down.",
+    ] {
+        text(&mut ops, "F1", TEXT_SIZE, (PAGE_MARGIN, y), line);
+        y += TEXT_SIZE * 1.5;
+    }
+
+    y += TEXT_SIZE * 2.0;
+    let _ = writeln!(ops, "0 G {MARK_WIDTH} w");
+    let _ = writeln!(
+        ops,
+        "{} {} m {} {} l S",
+        num(PAGE_MARGIN),
+        num(y),
+        num(PAGE_MARGIN + SCALE_BAR),
+        num(y)
+    );
+    for tick in 0..=10 {
+        let x = PAGE_MARGIN + tick as f32 * SCALE_BAR / 10.0;
+        let length = if tick % 5 == 0 { 3.0 } else { 1.5 };
+        let _ = writeln!(
+            ops,
+            "{} {} m {} {} l S",
+            num(x),
+            num(y),
+            num(x),
+            num(y - length)
+        );
+    }
+    text(
+        &mut ops,
+        "F1",
+        SMALL_SIZE,
+        (PAGE_MARGIN + SCALE_BAR + 3.0, y),
+        &format!("{SCALE_BAR:.0} mm"),
+    );
+    y += TEXT_SIZE * 2.0;
+
+    let room = (view.0, page_height - PAGE_MARGIN - y - SMALL_SIZE * 2.0);
+    let scale = (room.0 / sheet.0).min(room.1 / sheet.1).min(1.0);
+    if room.1 > 10.0 {
+        let origin = (PAGE_MARGIN, y + SMALL_SIZE * 2.0);
+        let _ = writeln!(
+            ops,
+            "q {s} 0 0 {s} {} {} cm /Comb Do Q",
+            num(origin.0 + pad * scale),
+            num(origin.1 + pad * scale),
+            s = num(scale)
+        );
+        let _ = writeln!(ops, "0.5 G {OUTLINE_WIDTH} w");
+        for row in 0..rows {
+            for column in 0..columns {
+                let left = origin.0 + column as f32 * step.0 * scale;
+                let top = origin.1 + row as f32 * step.1 * scale;
+                let (w, h) = (
+                    view.0.min(sheet.0 - column as f32 * step.0) * scale,
+                    view.1.min(sheet.1 - row as f32 * step.1) * scale,
+                );
+                let _ = writeln!(ops, "{} {} {} {} re S", num(left), num(top), num(w), num(h));
+                let label = page_number(column, row).to_string();
+                let label_x = left + w / 2.0 - label.len() as f32 * SMALL_SIZE * 0.28;
+                text(&mut ops, "F1", SMALL_SIZE, (label_x, top - 1.0), &label);
+            }
+        }
+    }
+    ops.push_str("Q\n");
+    streams.push(ops);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let origin = (-pad + column as f32 * step.0, -pad + row as f32 * step.1);
+            let (right, bottom) = (PAGE_MARGIN + view.0, PAGE_MARGIN + view.1);
+            let mut ops = page_start(page_height);
+
+            // The next page to the right and below covers these strips
+            ops.push_str("0.9 g\n");
+            if column + 1 < columns {
+                let _ = writeln!(
+                    ops,
+                    "{} {} {} {} re f",
+                    num(right - overlap),
+                    num(PAGE_MARGIN),
+                    num(overlap),
+                    num(view.1)
+                );
+            }
+            if row + 1 < rows {
+                let _ = writeln!(
+                    ops,
+                    "{} {} {} {} re f",
+                    num(PAGE_MARGIN),
+                    num(bottom - overlap),
+                    num(view.0),
+                    num(overlap)
+                );
+            }
+            let _ = writeln!(
+                ops,
+                "0 g q {} {} {} {} re W n 1 0 0 1 {} {} cm /Comb Do Q",
+                num(PAGE_MARGIN),
+                num(PAGE_MARGIN),
+                num(view.0),
+                num(view.1),
+                num(PAGE_MARGIN - origin.0),
+                num(PAGE_MARGIN - origin.1)
+            );
+
+            // Trim lines through the seams shared with the pages before this one
+            let _ = writeln!(ops, "0.4 G {MARK_WIDTH} w [2 1] 0 d");
+            let seam = PAGE_MARGIN + overlap / 2.0;
+            if column > 0 {
+                let _ = writeln!(
+                    ops,
+                    "{} {} m {} {} l S",
+                    num(seam),
+                    num(PAGE_MARGIN),
+                    num(seam),
+                    num(bottom)
+                );
+            }
+            if row > 0 {
+                let _ = writeln!(
+                    ops,
+                    "{} {} m {} {} l S",
+                    num(PAGE_MARGIN),
+                    num(seam),
+                    num(right),
+                    num(seam)
+                );
+            }
+            ops.push_str("[] 0 d\n");
+
+            let number = page_number(column, row);
+            let mut footer = format!("Page {number} of {total}");
+            if !cover.title.is_empty() {
+                footer = format!("{} - {footer}", cover.title);
+            }
+            if rows > 1 {
+                let _ = write!(footer, " (row {}, column {})", row + 1, column + 1);
+            }
+            let over: Vec<String> = [
+                (column > 0).then(|| page_number(column - 1, row)),
+                (row > 0).then(|| page_number(column, row - 1)),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|n| n.to_string())
+            .collect();
+            let under: Vec<String> = [
+                (column + 1 < columns).then(|| page_number(column + 1, row)),
+                (row + 1 < rows).then(|| page_number(column, row + 1)),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|n| n.to_string())
+            .collect();
+            if !over.is_empty() {
+                let _ = write!(footer, " - lay over page {}", over.join(" and "));
+            }
+            if !under.is_empty() {
+                let _ = write!(footer, " - page {} goes on top", under.join(" and "));
+            }
+            text(
+                &mut ops,
+                "F1",
+                SMALL_SIZE,
+                (PAGE_MARGIN, page_height - PAGE_MARGIN - 1.0),
+                &footer,
+            );
+            ops.push_str("Q\n");
+            streams.push(ops);
+        }
+    }
+
+    let media_box = format!(
+        "[0 0 {} {}]",
+        num(page_width * PT_PER_MM),
+        num(page_height * PT_PER_MM)
+    );
+    let kids: Vec<String> = streams
+        .iter()
+        .map(|ops| {
+            let contents = document.stream("", ops);
+            let page = document.add(format!(
+                "<< /Type /Page /Parent {pages} 0 R /MediaBox {media_box} /Resources \
+                 {resources} /Contents {contents} 0 R >>"
+            ));
+            format!("{page} 0 R")
+        })
+        .collect();
+    document.set(
+        pages,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        ),
+    );
+    Ok(document.finish(catalog, info))
+}
+
+/// Page stream opening: millimetres from the top-left corner, Y down.
+fn page_start(page_height: f32) -> String {
+    format!(
+        "q {s} 0 0 -{s} 0 {} cm\n",
+        num(page_height * PT_PER_MM),
+        s = num(PT_PER_MM)
+    )
+}
+
+/// Text with its baseline starting at `at`, upright despite the flipped page space.
+fn text(ops: &mut String, font: &str, size: f32, (x, y): Point, content: &str) {
+    let _ = writeln!(
+        ops,
+        "BT /{font} {} Tf 1 0 0 -1 {} {} Tm {} Tj ET",
+        num(size),
+        num(x),
+        num(y),
+        pdf_string(content)
+    );
+}
+
+fn path(ops: &mut String, points: &[Point]) {
+    for (i, &(x, y)) in points.iter().enumerate() {
+        let op = if i == 0 { "m" } else { "l" };
+        let _ = writeln!(ops, "{} {} {op}", num(x), num(y));
+    }
+}
+
+/// A circle with a cross through it, centred on `(x, y)`.
+fn draw_target(ops: &mut String, (x, y): Point) {
+    // Four Bézier quarter arcs; this handle length keeps them within 0.03% of a circle
+    let (r, k) = (TARGET_RADIUS, TARGET_RADIUS * 0.5523);
+    let _ = writeln!(ops, "{} {} m", num(x + r), num(y));
+    for (dx, dy) in [(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0)] {
+        let _ = writeln!(
+            ops,
+            "{} {} {} {} {} {} c",
+            num(x + if dx == dy { r * dx } else { k * dx }),
+            num(y + if dx == dy { k * dy } else { r * dy }),
+            num(x + if dx == dy { k * dx } else { r * dx }),
+            num(y + if dx == dy { r * dy } else { k * dy }),
+            num(x + if dx == dy { 0.0 } else { r * dx }),
+            num(y + if dx == dy { r * dy } else { 0.0 })
+        );
+    }
+    let reach = r * 1.4;
+    let _ = writeln!(
+        ops,
+        "S {} {} m {} {} l S {} {} m {} {} l S",
+        num(x - reach),
+        num(y),
+        num(x + reach),
+        num(y),
+        num(x),
+        num(y - reach),
+        num(x),
+        num(y + reach)
+    );
+}
+
+fn num(value: f32) -> String {
+    let text = format!("{value:.3}");
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" { "0" } else { text }.to_string()
+}
+
+/// A literal string in the fonts' WinAnsi encoding. Characters outside Latin-1 become `?`.
+fn pdf_string(content: &str) -> String {
+    let mut out = String::from("(");
+    for c in content.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+/// Numbered PDF objects, written out with their cross-reference table.
+#[derive(Default)]
+struct Document {
+    objects: Vec<String>,
+}
+
+impl Document {
+    fn add(&mut self, body: impl Into<String>) -> usize {
+        self.objects.push(body.into());
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, body: String) {
+        self.objects[id - 1] = body;
+    }
+
+    fn stream(&mut self, dictionary: &str, data: &str) -> usize {
+        self.add(format!(
+            "<< {dictionary} /Length {} >>\nstream\n{data}\nendstream",
+            data.len()
+        ))
+    }
+
+    fn finish(self, root: usize, info: usize) -> Vec<u8> {
+        let mut out = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, body) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            let _ = write!(out, "{} 0 obj\n{body}\nendobj\n", i + 1);
+        }
+        let xref = out.len();
+        let _ = write!(
+            out,
+            "xref\n0 {}\n0000000000 65535 f \n",
+            self.objects.len() + 1
+        );
+        for offset in offsets {
+            let _ = writeln!(out, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {} /Root {root} 0 R /Info {info} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            self.objects.len() + 1
+        );
+        out.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cover(title: &str) -> Cover {
+        Cover {
+            title: title.to_string(),
+            details: vec![("Tracks".to_string(), "Lead (right hand)".to_string())],
+        }
+    }
+
+    /// A plain `width` × `height` mm comb.
+    fn comb(width: f32, height: f32, settings: &PdfSettings) -> String {
+        let outline = vec![(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+        let pdf = render(&[outline], (width, height), 0.0, &cover("Song"), settings).unwrap();
+        String::from_utf8(pdf).unwrap()
+    }
+
+    fn page_count(pdf: &str) -> usize {
+        let count = pdf.split("/Type /Pages").nth(1).unwrap();
+        let count = count.split("/Count ").nth(1).unwrap();
+        count.split(' ').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn pages_tile_the_comb_with_its_overlap() {
+        // A4 landscape shows 277 × 183 mm and moves on 267 × 173 mm a page. With 8 mm for
+        // the targets all round, 500 mm of comb spans two pages and 600 mm three.
+        let landscape = PdfSettings::default();
+        assert_eq!(page_count(&comb(500.0, 12.0, &landscape)), 1 + 2);
+        assert_eq!(page_count(&comb(600.0, 12.0, &landscape)), 1 + 3);
+
+        // Portrait moves on 180 × 260 mm: three columns of two rows
+        let portrait = PdfSettings {
+            landscape: false,
+            ..PdfSettings::default()
+        };
+        let pdf = comb(500.0, 300.0, &portrait);
+        assert_eq!(page_count(&pdf), 1 + 6);
+        assert_eq!(pdf.matches("/Type /Page /Parent").count(), 7);
+        assert!(pdf.contains("(Song - Page 6 of 6 \\(row 2, column 3\\) - lay over page 5 and 3)"));
+
+        // No overlap, no extra pages
+        let tight = PdfSettings {
+            overlap: 0.0,
+            ..PdfSettings::default()
+        };
+        assert_eq!(page_count(&comb(277.0 - 16.0, 12.0, &tight)), 1 + 1);
+    }
+
+    #[test]
+    fn an_overlap_of_half_the_page_is_refused() {
+        let settings = PdfSettings {
+            overlap: 100.0,
+            ..PdfSettings::default()
+        };
+        let outline = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 5.0)];
+        assert!(matches!(
+            render(&[outline], (10.0, 5.0), 0.0, &cover("Song"), &settings),
+            Err(PdfError::OverlapTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn xref_offsets_point_at_their_objects() {
+        let pdf = comb(600.0, 30.0, &PdfSettings::default());
+        let xref = pdf.rfind("\nxref\n").unwrap() + 1;
+        let startxref: usize = pdf
+            .split("startxref\n")
+            .nth(1)
+            .and_then(|rest| rest.lines().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(startxref, xref);
+
+        let mut lines = pdf[xref..].lines().skip(1);
+        let size: usize = lines.next().unwrap()[2..].parse().unwrap();
+        assert_eq!(lines.next(), Some("0000000000 65535 f "));
+        for id in 1..size {
+            let entry = lines.next().unwrap();
+            assert_eq!(entry.len(), 19, "{entry:?}");
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(
+                pdf[offset..].starts_with(&format!("{id} 0 obj\n")),
+                "object {id} at {offset}"
+            );
+        }
+        assert_eq!(lines.next(), Some("trailer"));
+    }
+
+    #[test]
+    fn strings_escape_delimiters_and_encode_latin_1() {
+        assert_eq!(
+            pdf_string(r"Suite (No. 1) \ Prélude"),
+            r"(Suite \(No. 1\) \\ Pr\351lude)"
+        );
+        assert_eq!(pdf_string("Ω"), "(?)");
+
+        let outline = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 5.0)];
+        let pdf = render(
+            &[outline],
+            (10.0, 5.0),
+            0.0,
+            &cover(r"a\b (c)"),
+            &PdfSettings::default(),
+        )
+        .unwrap();
+        let pdf = String::from_utf8(pdf).unwrap();
+        assert!(pdf.contains(r"/Title (a\\b \(c\))"));
+        assert!(pdf.contains(r"(Lead \(right hand\)) Tj"));
+    }
+}
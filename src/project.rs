@@ -0,0 +1,361 @@
+//! Project files: a comb design saved as JSON, so a session can be picked up where it was left.
+//!
+//! A project points at its MIDI file (relative to the project when it sits beside it) or
+//! carries the notes itself, and stores the selected track, every setting and the note edits
+//! made on top of the source. Files are versioned; newer versions are refused rather than
+//! half-read, and fields missing from older files take their defaults.
+
+use crate::comb::CombSettings;
+use crate::midi::{self, LoadError, LoadOptions, MidiNote, Song};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Format version written by this build.
+pub const PROJECT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NoteSource {
+    /// A MIDI file, re-read on open. Relative paths are relative to the project file.
+    Midi { path: PathBuf },
+    /// The notes themselves, for projects that must not depend on the original file.
+    Embedded { song: Song },
+}
+
+/// A change to one note of the source, addressed by its index in the unedited track.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoteEdit {
+    pub track: usize,
+    pub note: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<u8>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub muted: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub source: NoteSource,
+    /// How the MIDI source is split into tracks; track and note indices depend on it.
+    #[serde(default)]
+    pub load_options: LoadOptions,
+    /// Track shown in the note editor.
+    #[serde(default)]
+    pub selected_track: usize,
+    /// Tracks merged into the comb. Empty means `selected_track` alone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub selected_tracks: Vec<usize>,
+    #[serde(default)]
+    pub settings: CombSettings,
+    #[serde(default)]
+    pub edits: Vec<NoteEdit>,
+    /// Preview zoom in pixels per mm, restored by the designer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview_px_per_mm: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    Midi(LoadError),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Io(e) => write!(f, "could not access project file: {e}"),
+            ProjectError::Format(e) => write!(f, "not a valid project file: {e}"),
+            ProjectError::UnsupportedVersion(v) => write!(
+                f,
+                "project version {v} is newer than this program supports ({PROJECT_VERSION})"
+            ),
+            ProjectError::Midi(e) => write!(f, "could not load the project's MIDI file: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProjectError::Io(e) => Some(e),
+            ProjectError::Format(e) => Some(e),
+            ProjectError::UnsupportedVersion(_) => None,
+            ProjectError::Midi(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProjectError {
+    fn from(e: std::io::Error) -> Self {
+        ProjectError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(e: serde_json::Error) -> Self {
+        ProjectError::Format(e)
+    }
+}
+
+impl From<LoadError> for ProjectError {
+    fn from(e: LoadError) -> Self {
+        ProjectError::Midi(e)
+    }
+}
+
+/// True when `path` names a project file rather than a MIDI file.
+pub fn is_project_path(path: &Path) -> bool {
+    path.to_string_lossy()
+        .to_ascii_lowercase()
+        .ends_with(".json")
+}
+
+impl Project {
+    pub fn new(source: NoteSource, settings: CombSettings) -> Self {
+        Self {
+            version: PROJECT_VERSION,
+            source,
+            load_options: LoadOptions::default(),
+            selected_track: 0,
+            selected_tracks: Vec::new(),
+            settings,
+            edits: Vec::new(),
+            preview_px_per_mm: None,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ProjectError> {
+        let text = std::fs::read_to_string(path)?;
+        let project: Project = serde_json::from_str(&text)?;
+        if project.version > PROJECT_VERSION {
+            return Err(ProjectError::UnsupportedVersion(project.version));
+        }
+        Ok(project)
+    }
+
+    /// Writes the project, storing the MIDI path relative to `path` when it sits below it.
+    pub fn save(&self, path: &Path) -> Result<(), ProjectError> {
+        let mut project = self.clone();
+        project.version = PROJECT_VERSION;
+        if let NoteSource::Midi { path: midi } = &mut project.source
+            && let Some(dir) = path.parent()
+            && let Ok(relative) = midi.strip_prefix(dir)
+        {
+            *midi = relative.to_path_buf();
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&project)?)?;
+        Ok(())
+    }
+
+    /// Absolute path of the source MIDI file, if the project has one.
+    pub fn midi_path(&self, project_path: &Path) -> Option<PathBuf> {
+        match &self.source {
+            NoteSource::Midi { path } => Some(resolve(project_path, path)),
+            NoteSource::Embedded { .. } => None,
+        }
+    }
+
+    /// The unedited notes: the MIDI file read afresh, or the embedded copy.
+    pub fn source_song(&self, project_path: &Path) -> Result<Song, ProjectError> {
+        match &self.source {
+            NoteSource::Midi { path } => Ok(midi::load_midi(
+                &resolve(project_path, path),
+                &self.load_options,
+            )?),
+            NoteSource::Embedded { song } => Ok(song.clone()),
+        }
+    }
+
+    /// Tracks the comb is made from, as saved or implied by `selected_track`.
+    pub fn comb_tracks(&self) -> Vec<usize> {
+        if self.selected_tracks.is_empty() {
+            vec![self.selected_track]
+        } else {
+            self.selected_tracks.clone()
+        }
+    }
+}
+
+fn resolve(project_path: &Path, midi_path: &Path) -> PathBuf {
+    match project_path.parent() {
+        Some(dir) if midi_path.is_relative() => dir.join(midi_path),
+        _ => midi_path.to_path_buf(),
+    }
+}
+
+/// Notes of track `track` with the project's edits applied. Muted notes are left out.
+pub fn apply_edits(notes: &[MidiNote], track: usize, edits: &[NoteEdit]) -> Vec<MidiNote> {
+    let mut edited = notes.to_vec();
+    let mut muted = vec![false; notes.len()];
+    for edit in edits.iter().filter(|e| e.track == track) {
+        let Some(note) = edited.get_mut(edit.note) else {
+            continue;
+        };
+        if let Some(pitch) = edit.pitch {
+            note.pitch = pitch.min(127);
+        }
+        muted[edit.note] = edit.muted;
+    }
+    let mut keep = muted.iter().map(|m| !m);
+    edited.retain(|_| keep.next().unwrap_or(true));
+    edited
+}
+
+/// Records `edit`, replacing any earlier edit of the same note. An edit that changes nothing
+/// is dropped, so undoing a change by hand leaves no trace in the project.
+pub fn set_edit(edits: &mut Vec<NoteEdit>, edit: NoteEdit) {
+    edits.retain(|e| (e.track, e.note) != (edit.track, edit.note));
+    if edit.pitch.is_some() || edit.muted {
+        edits.push(edit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::TrackData;
+
+    /// An empty directory of its own under the system temp directory.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("midi_to_bar-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn note(pitch: u8, start: f32) -> MidiNote {
+        MidiNote {
+            pitch,
+            start_time: start,
+            duration: 1.0,
+            start_seconds: start / 2.0,
+            duration_seconds: 0.5,
+            bend: Vec::new(),
+            soft: false,
+        }
+    }
+
+    fn edit(note: usize, pitch: Option<u8>, muted: bool) -> NoteEdit {
+        NoteEdit {
+            track: 0,
+            note,
+            pitch,
+            muted,
+        }
+    }
+
+    #[test]
+    fn saved_project_loads_back_the_same() {
+        let dir = scratch("round-trip");
+        let path = dir.join("comb.json");
+        let song = Song {
+            tracks: vec![TrackData::new(
+                "Lead".to_string(),
+                vec![note(60, 0.0), note(64, 1.0)],
+            )],
+            initial_bpm: 96.0,
+            has_tempo_changes: false,
+            warnings: Vec::new(),
+        };
+        let mut project = Project::new(
+            NoteSource::Embedded { song },
+            CombSettings {
+                drag_speed: 150.0,
+                multi_lane: true,
+                ..CombSettings::default()
+            },
+        );
+        project.selected_tracks = vec![0];
+        project.edits = vec![edit(1, Some(65), false)];
+        project.preview_px_per_mm = Some(4.0);
+        project.save(&path).unwrap();
+
+        let loaded = Project::load(&path).unwrap();
+        assert_eq!(loaded.version, PROJECT_VERSION);
+        assert_eq!(loaded.settings, project.settings);
+        assert_eq!(loaded.selected_tracks, [0]);
+        assert_eq!(loaded.edits, project.edits);
+        assert_eq!(loaded.preview_px_per_mm, Some(4.0));
+        let NoteSource::Embedded { song } = loaded.source else {
+            panic!("the embedded song was not kept");
+        };
+        assert_eq!(song.initial_bpm, 96.0);
+        assert_eq!(song.tracks[0].notes, [note(60, 0.0), note(64, 1.0)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn midi_beside_the_project_is_saved_relative_to_it() {
+        let dir = scratch("relative");
+        let path = dir.join("comb.json");
+        let midi = dir.join("songs").join("tune.mid");
+        let outside = PathBuf::from("/elsewhere/tune.mid");
+        for (source, saved) in [(&midi, Path::new("songs/tune.mid")), (&outside, &outside)] {
+            Project::new(
+                NoteSource::Midi {
+                    path: source.clone(),
+                },
+                CombSettings::default(),
+            )
+            .save(&path)
+            .unwrap();
+            let loaded = Project::load(&path).unwrap();
+            let NoteSource::Midi { path: stored } = &loaded.source else {
+                panic!("the MIDI source was not kept");
+            };
+            assert_eq!(stored, saved);
+            assert_eq!(loaded.midi_path(&path).as_ref(), Some(source));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_refused_and_missing_fields_defaulted() {
+        let dir = scratch("version");
+        let path = dir.join("comb.json");
+        let source = r#""source": { "kind": "midi", "path": "tune.mid" }"#;
+        std::fs::write(&path, format!(r#"{{ "version": 99, {source} }}"#)).unwrap();
+        assert!(matches!(
+            Project::load(&path),
+            Err(ProjectError::UnsupportedVersion(99))
+        ));
+
+        std::fs::write(&path, format!(r#"{{ "version": 1, {source} }}"#)).unwrap();
+        let project = Project::load(&path).unwrap();
+        assert_eq!(project.settings, CombSettings::default());
+        assert_eq!(project.comb_tracks(), [0]);
+        assert!(project.edits.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn edits_change_pitch_and_leave_muted_notes_out() {
+        let notes = [note(60, 0.0), note(62, 1.0), note(64, 2.0)];
+        let edits = [
+            edit(0, Some(72), false),
+            edit(1, None, true),
+            edit(7, Some(50), false), // Past the end of the track
+            NoteEdit {
+                track: 1,
+                ..edit(2, Some(40), false)
+            },
+        ];
+        let edited = apply_edits(&notes, 0, &edits);
+        let pitches: Vec<u8> = edited.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, [72, 64]);
+        assert_eq!(edited[1].start_time, 2.0);
+    }
+
+    #[test]
+    fn setting_an_edit_replaces_the_last_and_drops_no_ops() {
+        let mut edits = vec![edit(0, Some(72), false), edit(1, None, true)];
+        set_edit(&mut edits, edit(0, Some(74), false));
+        assert_eq!(edits, [edit(1, None, true), edit(0, Some(74), false)]);
+        set_edit(&mut edits, edit(1, None, false));
+        assert_eq!(edits, [edit(0, Some(74), false)]);
+    }
+}
@@ -0,0 +1,370 @@
+//! Bitmap rendering of comb outlines, for printers and cutters that only take raster files.
+//!
+//! The comb is filled black on white at exactly the requested resolution, so a pixel count
+//! divided by the DPI gives millimetres on the page. PNG files carry the resolution in their
+//! `pHYs` chunk and TIFF files in their resolution tags, which print dialogs use to keep
+//! the comb at true scale.
+
+use crate::geometry::Point;
+use crate::strip::StripError;
+use image::{GrayImage, Luma};
+use imageproc::drawing::{draw_antialiased_polygon_mut, draw_filled_rect_mut, draw_polygon_mut};
+use imageproc::rect::Rect;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RasterFormat {
+    Png,
+    Tiff,
+}
+
+impl RasterFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RasterFormat::Png => "png",
+            RasterFormat::Tiff => "tif",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RasterSettings {
+    pub dpi: u32,
+    /// Pure black and white, stored as one bit per pixel.
+    pub one_bit: bool,
+    /// Corner marks around the comb, for trimming the print.
+    pub crop_marks: bool,
+}
+
+impl Default for RasterSettings {
+    fn default() -> Self {
+        Self {
+            dpi: 600,
+            one_bit: false,
+            crop_marks: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RasterError {
+    NothingToDraw,
+    InvalidDpi,
+    TooLarge { width: u64, height: u64 },
+    Encode(String),
+    Strips(StripError),
+}
+
+impl fmt::Display for RasterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RasterError::NothingToDraw => {
+                write!(f, "Nothing to draw: no notes in the selected track.")
+            }
+            RasterError::InvalidDpi => write!(f, "Resolution must be greater than zero."),
+            RasterError::TooLarge { width, height } => write!(
+                f,
+                "The image would be {width} × {height} pixels. Use a lower resolution or a \
+                 faster drag speed."
+            ),
+            RasterError::Encode(message) => write!(f, "Could not encode the image: {message}"),
+            RasterError::Strips(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+impl From<StripError> for RasterError {
+    fn from(e: StripError) -> Self {
+        RasterError::Strips(e)
+    }
+}
+
+/// Blank stock left around the comb when crop marks are drawn, in mm.
+const CROP_MARGIN: f32 = 6.0;
+/// Space between a comb corner and its crop marks, in mm.
+const CROP_GAP: f32 = 1.0;
+const CROP_LENGTH: f32 = 4.0;
+const CROP_WIDTH: f32 = 0.25;
+/// Largest image rendered, in pixels: the canvas takes a byte per pixel. A three-minute
+/// comb at 200 mm/s and 600 dpi is under half of this.
+const MAX_PIXELS: u64 = 1 << 29;
+
+/// Fills `outlines` (mm, Y down) inside a `width` × `height` mm area and encodes the result.
+///
+/// `bleed` is how far outlines may reach past the area, from kerf compensation; the image
+/// grows by that much on every side so nothing is clipped.
+pub fn render(
+    outlines: &[Vec<Point>],
+    (width, height): (f32, f32),
+    bleed: f32,
+    format: RasterFormat,
+    settings: &RasterSettings,
+) -> Result<Vec<u8>, RasterError> {
+    if outlines.is_empty() {
+        return Err(RasterError::NothingToDraw);
+    }
+    if settings.dpi == 0 {
+        return Err(RasterError::InvalidDpi);
+    }
+    let px_per_mm = settings.dpi as f32 / 25.4;
+    let crop_margin = if settings.crop_marks {
+        CROP_MARGIN
+    } else {
+        0.0
+    };
+    let margin = bleed.max(0.0) + crop_margin;
+    let pixels = |mm: f32| ((mm + 2.0 * margin) * px_per_mm).ceil().max(1.0) as u64;
+    let (image_width, image_height) = (pixels(width), pixels(height));
+    if image_width * image_height > MAX_PIXELS
+        || image_width > u32::MAX as u64
+        || image_height > u32::MAX as u64
+    {
+        return Err(RasterError::TooLarge {
+            width: image_width,
+            height: image_height,
+        });
+    }
+
+    let mut image = GrayImage::from_pixel(image_width as u32, image_height as u32, Luma([255]));
+    let black = Luma([0u8]);
+    let to_px = |mm: f32| ((mm + margin) * px_per_mm).round() as i32;
+    for outline in outlines {
+        let mut polygon: Vec<imageproc::point::Point<i32>> = Vec::with_capacity(outline.len());
+        for &(x, y) in outline {
+            let point = imageproc::point::Point::new(to_px(x), to_px(y));
+            if polygon.last() != Some(&point) {
+                polygon.push(point);
+            }
+        }
+        // The polygon is closed implicitly and must not repeat its first point
+        while polygon.len() > 1 && polygon.first() == polygon.last() {
+            polygon.pop();
+        }
+        if polygon.len() < 3 {
+            continue;
+        }
+        if settings.one_bit {
+            draw_polygon_mut(&mut image, &polygon, black);
+        } else {
+            draw_antialiased_polygon_mut(
+                &mut image,
+                &polygon,
+                black,
+                imageproc::pixelops::interpolate,
+            );
+        }
+    }
+
+    if settings.crop_marks {
+        let line = ((CROP_WIDTH * px_per_mm).round() as u32).max(1);
+        let length = ((CROP_LENGTH * px_per_mm).round() as u32).max(1);
+        let (gap, reach) = (CROP_GAP, CROP_GAP + CROP_LENGTH);
+        for (x, y) in [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)] {
+            // Each corner gets one horizontal and one vertical stroke pointing away from it
+            let out_x = if x == 0.0 { -reach } else { gap };
+            let out_y = if y == 0.0 { -reach } else { gap };
+            let horizontal =
+                Rect::at(to_px(x + out_x), to_px(y) - line as i32 / 2).of_size(length, line);
+            let vertical =
+                Rect::at(to_px(x) - line as i32 / 2, to_px(y + out_y)).of_size(line, length);
+            draw_filled_rect_mut(&mut image, horizontal, black);
+            draw_filled_rect_mut(&mut image, vertical, black);
+        }
+    }
+
+    if settings.one_bit {
+        for pixel in image.pixels_mut() {
+            pixel.0[0] = if pixel.0[0] < 128 { 0 } else { 255 };
+        }
+    }
+    match format {
+        RasterFormat::Png => encode_png(&image, settings),
+        RasterFormat::Tiff => encode_tiff(&image, settings),
+    }
+}
+
+fn encode_png(image: &GrayImage, settings: &RasterSettings) -> Result<Vec<u8>, RasterError> {
+    let encode = |e: png::EncodingError| RasterError::Encode(e.to_string());
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, image.width(), image.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: (settings.dpi as f64 / 0.0254).round() as u32,
+        yppu: (settings.dpi as f64 / 0.0254).round() as u32,
+        unit: png::Unit::Meter,
+    }));
+    let data = if settings.one_bit {
+        encoder.set_depth(png::BitDepth::One);
+        // Grey PNGs count up from black, so white pixels are the set bits
+        pack_bits(image, |v| v != 0)
+    } else {
+        encoder.set_depth(png::BitDepth::Eight);
+        image.as_raw().clone()
+    };
+    let mut writer = encoder.write_header().map_err(encode)?;
+    writer.write_image_data(&data).map_err(encode)?;
+    writer.finish().map_err(encode)?;
+    Ok(bytes)
+}
+
+/// Eight pixels to a byte, most significant first, each row padded to a whole byte. `set`
+/// says which grey values become a 1 bit.
+fn pack_bits(image: &GrayImage, set: impl Fn(u8) -> bool) -> Vec<u8> {
+    image
+        .rows()
+        .flat_map(|row| {
+            let row: Vec<u8> = row.map(|p| p.0[0]).collect();
+            row.chunks(8)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u8, |byte, (i, &v)| byte | (u8::from(set(v)) << (7 - i)))
+                })
+                .collect::<Vec<u8>>()
+        })
+        .collect()
+}
+
+/// Bilevel TIFF sample layout: one bit per pixel with black as 1, the usual convention for
+/// black and white scans and faxes.
+struct Gray1;
+
+impl tiff::encoder::colortype::ColorType for Gray1 {
+    type Inner = u8;
+    const TIFF_VALUE: tiff::tags::PhotometricInterpretation =
+        tiff::tags::PhotometricInterpretation::WhiteIsZero;
+    const BITS_PER_SAMPLE: &'static [u16] = &[1];
+    const SAMPLE_FORMAT: &'static [tiff::tags::SampleFormat] = &[tiff::tags::SampleFormat::Uint];
+
+    fn horizontal_predict(row: &[u8], result: &mut Vec<u8>) {
+        // Never asked for: the encoder is used without a predictor
+        result.extend_from_slice(row);
+    }
+}
+
+/// Grey TIFF at eight bits per pixel, or bilevel at one bit when `one_bit` is set.
+fn encode_tiff(image: &GrayImage, settings: &RasterSettings) -> Result<Vec<u8>, RasterError> {
+    use tiff::encoder::{Compression, Rational, TiffEncoder, colortype};
+    use tiff::tags::{ResolutionUnit, Tag};
+
+    let encode = |e: tiff::TiffError| RasterError::Encode(e.to_string());
+    let resolution = Rational {
+        n: settings.dpi,
+        d: 1,
+    };
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut bytes)
+        .map_err(encode)?
+        .with_compression(Compression::Packbits);
+    if settings.one_bit {
+        // The encoder counts a byte per sample, so it is given the packed row length as the
+        // width and the real width is set over it
+        let row_bytes = image.width().div_ceil(8);
+        let mut tiff = encoder
+            .new_image::<Gray1>(row_bytes, image.height())
+            .map_err(encode)?;
+        tiff.encoder()
+            .write_tag(Tag::ImageWidth, image.width())
+            .map_err(encode)?;
+        tiff.resolution(ResolutionUnit::Inch, resolution);
+        tiff.write_data(&pack_bits(image, |v| v == 0))
+            .map_err(encode)?;
+    } else {
+        let mut tiff = encoder
+            .new_image::<colortype::Gray8>(image.width(), image.height())
+            .map_err(encode)?;
+        tiff.resolution(ResolutionUnit::Inch, resolution);
+        tiff.write_data(image.as_raw()).map_err(encode)?;
+    }
+    Ok(bytes.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::Tag;
+
+    /// 10.3 × 5 mm at 254 dpi, ten pixels to the mm, with the left 5 mm black. 103 pixels
+    /// leave the last byte of each packed row partly used.
+    fn half_black(format: RasterFormat, one_bit: bool) -> Vec<u8> {
+        let square = vec![(0.0, 0.0), (5.0, 0.0), (5.0, 5.0), (0.0, 5.0)];
+        let settings = RasterSettings {
+            dpi: 254,
+            one_bit,
+            crop_marks: false,
+        };
+        render(&[square], (10.3, 5.0), 0.0, format, &settings).unwrap()
+    }
+
+    #[test]
+    fn png_keeps_size_resolution_and_polarity() {
+        for one_bit in [false, true] {
+            let bytes = half_black(RasterFormat::Png, one_bit);
+            let mut reader = png::Decoder::new(std::io::Cursor::new(bytes))
+                .read_info()
+                .unwrap();
+            let info = reader.info();
+            assert_eq!((info.width, info.height), (103, 50));
+            assert_eq!(info.color_type, png::ColorType::Grayscale);
+            let dims = info.pixel_dims.unwrap();
+            assert_eq!((dims.xppu, dims.yppu), (10000, 10000));
+            assert_eq!(dims.unit, png::Unit::Meter);
+
+            let mut data = vec![0; reader.output_buffer_size().unwrap()];
+            let frame = reader.next_frame(&mut data).unwrap();
+            let value = |x: usize, y: usize| match frame.bit_depth {
+                png::BitDepth::One => (data[y * frame.line_size + x / 8] >> (7 - x % 8)) & 1,
+                _ => data[y * frame.line_size + x],
+            };
+            let white = if one_bit { 1 } else { 255 };
+            assert_eq!(frame.line_size, if one_bit { 13 } else { 103 });
+            assert_eq!(
+                (value(20, 25), value(80, 25), value(102, 49)),
+                (0, white, white)
+            );
+        }
+    }
+
+    #[test]
+    fn tiff_keeps_size_resolution_and_polarity() {
+        for one_bit in [false, true] {
+            let bytes = half_black(RasterFormat::Tiff, one_bit);
+            let mut decoder = Decoder::new(std::io::Cursor::new(bytes)).unwrap();
+            assert_eq!(decoder.dimensions().unwrap(), (103, 50));
+            assert_eq!(decoder.get_tag_u32(Tag::ResolutionUnit).unwrap(), 2); // Inch
+            assert_eq!(decoder.get_tag_u32_vec(Tag::XResolution).unwrap(), [254, 1]);
+            // WhiteIsZero for bilevel, BlackIsZero for grey
+            let photometric = decoder.get_tag_u32(Tag::PhotometricInterpretation).unwrap();
+            assert_eq!(photometric, if one_bit { 0 } else { 1 });
+            assert_eq!(
+                decoder.get_tag_u32(Tag::BitsPerSample).unwrap(),
+                if one_bit { 1 } else { 8 }
+            );
+
+            let DecodingResult::U8(data) = decoder.read_image().unwrap() else {
+                panic!("not 8-bit samples");
+            };
+            let row = if one_bit { 13 } else { 103 };
+            assert_eq!(data.len(), row * 50);
+            let value = |x: usize, y: usize| {
+                if one_bit {
+                    (data[y * row + x / 8] >> (7 - x % 8)) & 1
+                } else {
+                    data[y * row + x]
+                }
+            };
+            // The decoder applies WhiteIsZero, so black comes back as 0 either way
+            let white = if one_bit { 1 } else { 255 };
+            assert_eq!(
+                (value(20, 25), value(80, 25), value(102, 49)),
+                (0, white, white)
+            );
+        }
+    }
+}
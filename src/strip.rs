@@ -0,0 +1,374 @@
+//! Splitting a comb longer than the stock or the cutter bed into numbered pieces.
+//!
+//! Breaks are put in rests where there is one within reach, else where the note changes,
+//! else in whatever gap between teeth lets the piece run longest. Each break is cut with a
+//! connector in the spine, and the pieces are stacked one under the other for export.
+
+use crate::comb::{CombLayout, CombSettings, LaneLayout};
+use crate::geometry::{self, Point, RestTreatment};
+use std::fmt;
+
+/// Space kept between a break and the nearest tooth or notch where the gap allows, in mm.
+const CLEARANCE: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StripError {
+    /// A piece this short has no room for the socket at one end and the tab at the other.
+    TooShort { max: f32, shortest: f32 },
+}
+
+impl fmt::Display for StripError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StripError::TooShort { max, shortest } => write!(
+                f,
+                "Strips of at most {max:.1} mm are too short for their joints. Set a maximum \
+                 strip length of at least {shortest:.1} mm, or a shorter spine."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StripError {}
+
+/// One piece of a split comb, already moved to its place in the export.
+#[derive(Clone, Debug)]
+pub struct StripPiece {
+    pub number: usize,
+    /// Stretch of the comb it carries, in layout x. Its connector tab reaches past `end`.
+    pub start: f32,
+    pub end: f32,
+    /// Added to layout coordinates to place them on this piece.
+    pub offset: Point,
+    /// One closed outline per comb row, before kerf compensation.
+    pub outlines: Vec<Vec<Point>>,
+    /// Piece number for each row, to engrave with its text starting at the point.
+    pub labels: Vec<(Point, String)>,
+}
+
+impl StripPiece {
+    pub fn place(&self, (x, y): Point) -> Point {
+        (x + self.offset.0, y + self.offset.1)
+    }
+}
+
+/// Every piece of the comb, and the area they take up stacked together.
+#[derive(Clone, Debug)]
+pub struct CombStrips {
+    pub width: f32,
+    pub height: f32,
+    pub pieces: Vec<StripPiece>,
+}
+
+impl CombSettings {
+    /// Shortest `max_strip_length` a split comb can be cut to: a piece has to carry the
+    /// socket of the joint before it and the tab of the joint after it, with clearance.
+    pub fn shortest_strip_length(&self) -> f32 {
+        2.0 * (self.strip_joint.length(self.tooth_shape.spine) + CLEARANCE)
+    }
+
+    /// Where the comb is cut into pieces no longer than `max_strip_length`, in layout x.
+    /// Empty when it fits in one piece or splitting is off.
+    pub fn strip_breaks(&self, layout: &CombLayout) -> Result<Vec<f32>, StripError> {
+        let max = self.max_strip_length;
+        if max <= 0.0 || layout.width <= max {
+            return Ok(Vec::new());
+        }
+        let shortest = self.shortest_strip_length();
+        if max < shortest {
+            return Err(StripError::TooShort { max, shortest });
+        }
+        let shape = &self.tooth_shape;
+        let tab = self.strip_joint.length(shape.spine);
+        // Longest stretch of comb a piece can carry with its tab
+        let reach = max - tab;
+
+        // Where no row can be cut: its teeth, and the notches a socket would run into
+        let mut blocked: Vec<(f32, f32)> = Vec::new();
+        for lane in &layout.lanes {
+            blocked.extend(geometry::tooth_spans(&lane.teeth, layout.width, shape));
+            if self.rest_treatment == RestTreatment::Notches {
+                blocked.extend(lane.rests.iter().map(|&rest| {
+                    let (left, right, _) = geometry::notch_span(rest, shape);
+                    (left - tab - CLEARANCE, right + CLEARANCE)
+                }));
+            }
+        }
+        blocked.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // Gaps narrower than twice the clearance shrink to their middle
+        let mut free = Vec::new();
+        let mut last_right = 0.0f32;
+        for (left, right) in blocked.into_iter().chain([(layout.width, layout.width)]) {
+            if left > last_right {
+                let keep = CLEARANCE.min((left - last_right) / 2.0);
+                free.push((last_right + keep, left - keep));
+            }
+            last_right = last_right.max(right);
+        }
+        let free_at = |x: f32| {
+            let at = free.partition_point(|&(left, _)| left <= x);
+            at > 0 && x <= free[at - 1].1
+        };
+
+        let quiet = |x: f32| {
+            layout.lanes.iter().all(|lane| {
+                lane.rests
+                    .iter()
+                    .any(|&(left, right)| left <= x && x <= right)
+                    || lane.segments.last().is_none_or(|s| x >= s.1)
+            })
+        };
+        let note_changes: Vec<f32> = {
+            let mut starts: Vec<f32> = layout
+                .lanes
+                .iter()
+                .flat_map(|lane| lane.segments.iter().skip(1).map(|s| s.0))
+                .collect();
+            starts.sort_by(f32::total_cmp);
+            starts
+        };
+        // Rightmost break in `low..=high`: in a rest if any, else at a note change
+        let pick = |low: f32, high: f32| -> Option<f32> {
+            let gaps = free
+                .iter()
+                .filter(|&&(left, right)| right >= low && left <= high);
+            let ends = gaps.map(|&(left, right)| (left.max(low), right.min(high)));
+            let in_rest = ends.clone().map(|(_, right)| right).filter(|&x| quiet(x));
+            let at_change = note_changes
+                .iter()
+                .filter(|&&x| x >= low && x <= high)
+                .filter_map(|&x| {
+                    if free_at(x) {
+                        return Some(x);
+                    }
+                    // The nearest free point before the change
+                    let at = free.partition_point(|&(left, _)| left <= x);
+                    free.get(at.wrapping_sub(1))
+                        .map(|gap| gap.1)
+                        .filter(|&end| end >= low && x - end < CLEARANCE * 4.0)
+                });
+            in_rest
+                .reduce(f32::max)
+                .or_else(|| at_change.reduce(f32::max))
+                .or_else(|| ends.map(|(_, right)| right).reduce(f32::max))
+        };
+
+        let mut breaks = Vec::new();
+        let mut start = 0.0;
+        while layout.width - start > max {
+            let high = start + reach;
+            let cut = pick(start + reach / 2.0, high)
+                .or_else(|| pick(start + CLEARANCE, high))
+                .unwrap_or(high);
+            breaks.push(cut);
+            start = cut;
+        }
+        Ok(breaks)
+    }
+
+    /// Where text engraved on a row's spine starts when it begins at `x`, centred between
+    /// the teeth and the back edge.
+    pub fn spine_label_at(&self, lane: &LaneLayout, x: f32) -> Point {
+        (
+            x,
+            lane.bottom - (self.tooth_shape.spine - self.label_height()) / 2.0,
+        )
+    }
+
+    /// The comb cut into pieces at [`strip_breaks`](Self::strip_breaks), stacked top to
+    /// bottom `lane_gap` apart. A comb that fits in one piece is left as it is, unlabelled.
+    pub fn comb_strips(&self, layout: &CombLayout) -> Result<CombStrips, StripError> {
+        let breaks = self.strip_breaks(layout)?;
+        if breaks.is_empty() {
+            return Ok(CombStrips {
+                width: layout.width,
+                height: layout.height,
+                pieces: vec![StripPiece {
+                    number: 1,
+                    start: 0.0,
+                    end: layout.width,
+                    offset: (0.0, 0.0),
+                    outlines: layout.lanes.iter().map(|l| l.outline.clone()).collect(),
+                    labels: Vec::new(),
+                }],
+            });
+        }
+
+        let tab = self.strip_joint.length(self.tooth_shape.spine);
+        let ends: Vec<f32> = breaks
+            .iter()
+            .copied()
+            .chain(std::iter::once(layout.width))
+            .collect();
+        let mut pieces = Vec::with_capacity(ends.len());
+        let mut start = 0.0;
+        for (i, &end) in ends.iter().enumerate() {
+            let offset = (-start, i as f32 * (layout.height + self.lane_gap));
+            let first = i == 0;
+            let last = i + 1 == ends.len();
+            let mut outlines = Vec::with_capacity(layout.lanes.len());
+            let mut labels = Vec::with_capacity(layout.lanes.len());
+            for (lane_index, lane) in layout.lanes.iter().enumerate() {
+                let left_edge =
+                    (!first).then(|| self.strip_joint.edge(start, lane.base, lane.bottom));
+                let right_edge =
+                    (!last).then(|| self.strip_joint.edge(end, lane.base, lane.bottom));
+                let outline = geometry::piece_outline(
+                    &lane.outline,
+                    (start, end),
+                    left_edge.as_deref(),
+                    right_edge.as_deref(),
+                );
+                outlines.push(
+                    outline
+                        .into_iter()
+                        .map(|(x, y)| (x + offset.0, y + offset.1))
+                        .collect(),
+                );
+                // Clear of the socket
+                let label_x = start + if first { 1.0 } else { tab + 1.0 };
+                let text = if layout.lanes.len() > 1 {
+                    format!("{}-{}", i + 1, lane_index + 1)
+                } else {
+                    (i + 1).to_string()
+                };
+                let (x, y) = self.spine_label_at(lane, label_x);
+                labels.push(((x + offset.0, y + offset.1), text));
+            }
+            pieces.push(StripPiece {
+                number: i + 1,
+                start,
+                end,
+                offset,
+                outlines,
+                labels,
+            });
+            start = end;
+        }
+
+        let width = pieces
+            .iter()
+            .map(|p| p.end - p.start + if p.end < layout.width { tab } else { 0.0 })
+            .fold(0.0, f32::max);
+        let count = pieces.len() as f32;
+        Ok(CombStrips {
+            width,
+            height: count * layout.height + (count - 1.0) * self.lane_gap,
+            pieces,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Tooth;
+
+    /// A one-row layout 30 mm long. The default spine takes a 2.4 mm dovetail tab.
+    fn layout(
+        teeth: Vec<Tooth>,
+        segments: Vec<(f32, f32, u8)>,
+        rests: Vec<(f32, f32)>,
+    ) -> CombLayout {
+        CombLayout {
+            x_offset: 0.0,
+            width: 30.0,
+            height: 12.0,
+            lanes: vec![LaneLayout {
+                top: 0.0,
+                base: 8.0,
+                bottom: 12.0,
+                teeth,
+                outline: Vec::new(),
+                segments,
+                rests,
+            }],
+        }
+    }
+
+    /// Teeth 1 mm apart and half as wide, centred on the half millimetres, so the only free
+    /// points are the whole millimetres.
+    fn teeth_except(skip: impl Fn(f32) -> bool) -> Vec<Tooth> {
+        (0..30)
+            .map(|i| i as f32 + 0.5)
+            .filter(|&x| !skip(x))
+            .map(|x| Tooth { x, spacing: 1.0 })
+            .collect()
+    }
+
+    fn settings(max_strip_length: f32) -> CombSettings {
+        CombSettings {
+            max_strip_length,
+            ..CombSettings::default()
+        }
+    }
+
+    #[test]
+    fn breaks_prefer_a_rest_then_a_note_change_then_any_gap() {
+        // Each piece carries up to 17.6 mm of comb; breaks are looked for from 8.8 mm on
+        let settings = settings(20.0);
+        let two_notes = vec![(0.0, 10.0, 60), (10.0, 30.0, 62)];
+
+        let rest = layout(
+            teeth_except(|x| x > 12.0 && x < 14.0),
+            two_notes.clone(),
+            vec![(12.0, 14.0)],
+        );
+        assert_eq!(settings.strip_breaks(&rest), Ok(vec![13.75]));
+
+        let change = layout(teeth_except(|_| false), two_notes, Vec::new());
+        assert_eq!(settings.strip_breaks(&change), Ok(vec![10.0]));
+
+        let one_note = layout(teeth_except(|_| false), vec![(0.0, 30.0, 60)], Vec::new());
+        assert_eq!(settings.strip_breaks(&one_note), Ok(vec![17.0]));
+    }
+
+    #[test]
+    fn breaks_fall_back_to_the_longest_piece_without_a_gap() {
+        // One tooth covering the whole comb leaves nowhere clear to cut
+        let solid = layout(
+            vec![Tooth {
+                x: 15.0,
+                spacing: 60.0,
+            }],
+            vec![(0.0, 30.0, 60)],
+            Vec::new(),
+        );
+        let breaks = settings(20.0).strip_breaks(&solid).unwrap();
+        assert_eq!(breaks.len(), 1);
+        assert!((breaks[0] - 17.6).abs() < 1e-4, "{breaks:?}");
+    }
+
+    #[test]
+    fn pieces_never_exceed_the_maximum() {
+        let comb = layout(
+            teeth_except(|x| x > 12.0 && x < 14.0),
+            vec![(0.0, 10.0, 60), (10.0, 30.0, 62)],
+            vec![(12.0, 14.0)],
+        );
+        for max in [5.8, 6.0, 7.5, 10.0, 14.0, 20.0, 29.0] {
+            let settings = settings(max);
+            let tab = settings.strip_joint.length(settings.tooth_shape.spine);
+            let breaks = settings.strip_breaks(&comb).unwrap();
+            assert!(!breaks.is_empty(), "max {max}");
+            // Every piece but the last carries a tab past its end
+            let mut start = 0.0;
+            for &cut in &breaks {
+                assert!(cut - start + tab <= max + 1e-4, "max {max}: {breaks:?}");
+                start = cut;
+            }
+            assert!(comb.width - start <= max, "max {max}: {breaks:?}");
+        }
+    }
+
+    #[test]
+    fn maximum_too_short_for_the_joints_is_rejected() {
+        let comb = layout(teeth_except(|_| false), vec![(0.0, 30.0, 60)], Vec::new());
+        let settings = settings(5.0);
+        let Err(StripError::TooShort { max, shortest }) = settings.strip_breaks(&comb) else {
+            panic!("a 5 mm maximum was accepted");
+        };
+        assert_eq!(max, 5.0);
+        assert!((shortest - 5.8).abs() < 1e-4, "{shortest}");
+    }
+}
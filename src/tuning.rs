@@ -0,0 +1,557 @@
+//! Tunings: which frequency each MIDI key plays.
+//!
+//! A tuning is a Scala scale (`.scl`, the intervals of one period) laid over the keyboard by a
+//! keyboard mapping (`.kbm`, which key plays which degree and which key is the reference).
+//! The reference key sounds at the concert pitch set in [`CombSettings`](crate::CombSettings);
+//! every other key is placed relative to it by the scale. The format is described at
+//! <https://www.huygens-fokker.org/scala/scl_format.html>.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Intervals of one period of a scale, in cents above its first degree. The last entry is
+/// the period itself, usually an octave (1200 cents).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Scale {
+    pub description: String,
+    pub degrees: Vec<f64>,
+}
+
+/// How scale degrees are laid over the MIDI keys, as in a Scala `.kbm` file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyboardMapping {
+    pub first_key: u8,
+    pub last_key: u8,
+    /// Key that plays the first degree of the scale.
+    pub middle_key: u8,
+    /// Key tuned to the concert pitch.
+    pub reference_key: u8,
+    /// Reference frequency given in the file. Loading a `.kbm` adopts it as concert pitch.
+    pub reference_frequency: f64,
+    /// Scale degree the pattern repeats at; 0 means the scale's own period.
+    pub octave_degree: usize,
+    /// Degree played by each key of one repetition, counting from `middle_key`. `None` keys
+    /// are silent. An empty pattern maps consecutive keys to consecutive degrees.
+    pub pattern: Vec<Option<usize>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tuning {
+    /// Shown to the user: the scale's name, followed by the mapping's if one was loaded.
+    pub name: String,
+    /// Name of the scale, and of the keyboard mapping if one was loaded, that `name` is
+    /// built from. Projects saved before these were kept load with them empty.
+    #[serde(default)]
+    pub scale_name: String,
+    #[serde(default)]
+    pub mapping_name: Option<String>,
+    pub scale: Scale,
+    pub mapping: KeyboardMapping,
+}
+
+#[derive(Debug)]
+pub enum TuningError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for TuningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TuningError::Io(e) => write!(f, "could not read file: {e}"),
+            TuningError::Parse { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for TuningError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TuningError::Io(e) => Some(e),
+            TuningError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TuningError {
+    fn from(e: std::io::Error) -> Self {
+        TuningError::Io(e)
+    }
+}
+
+fn cents_of_ratio(ratio: f64) -> f64 {
+    1200.0 * ratio.log2()
+}
+
+/// Lines of a Scala file that carry data: comments dropped, numbered from 1 for errors.
+fn data_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('!'))
+        .map(|(i, line)| (i + 1, line.trim()))
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> TuningError {
+    TuningError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// First whitespace-separated word of a data line, which is all Scala reads from it.
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+impl Scale {
+    /// Twelve equal semitones per octave.
+    pub fn equal_temperament() -> Self {
+        Self {
+            description: "12-tone equal temperament".to_string(),
+            degrees: (1..=12).map(|i| i as f64 * 100.0).collect(),
+        }
+    }
+
+    /// Parses the contents of a `.scl` file. Pitches with a dot are cents; anything else is
+    /// a ratio such as `3/2`, or a whole number meaning `n/1`.
+    pub fn parse(text: &str) -> Result<Self, TuningError> {
+        let mut lines = data_lines(text);
+        // The description may be blank, so it is the only line not trimmed away as empty
+        let (_, description) = lines.next().ok_or_else(|| parse_error(1, "empty file"))?;
+        let mut lines = lines.filter(|(_, line)| !line.is_empty());
+        let (count_line, count) = lines
+            .next()
+            .ok_or_else(|| parse_error(2, "missing number of notes"))?;
+        let count: usize = first_word(count)
+            .parse()
+            .map_err(|_| parse_error(count_line, format!("bad number of notes: {count}")))?;
+
+        let mut degrees = Vec::with_capacity(count);
+        for (number, line) in lines.take(count) {
+            let word = first_word(line);
+            let cents = if word.contains('.') {
+                word.parse::<f64>().ok()
+            } else {
+                let (num, den) = word.split_once('/').unwrap_or((word, "1"));
+                match (num.parse::<f64>(), den.parse::<f64>()) {
+                    (Ok(num), Ok(den)) if num > 0.0 && den > 0.0 => Some(cents_of_ratio(num / den)),
+                    _ => None,
+                }
+            };
+            degrees.push(cents.ok_or_else(|| parse_error(number, format!("bad pitch: {word}")))?);
+        }
+        if degrees.len() != count {
+            return Err(parse_error(
+                text.lines().count(),
+                format!("expected {count} notes, found {}", degrees.len()),
+            ));
+        }
+        if count == 0 {
+            return Err(parse_error(count_line, "a scale needs at least one note"));
+        }
+        Ok(Self {
+            description: description.to_string(),
+            degrees,
+        })
+    }
+
+    /// Cents of a degree counted from the first, continuing into later periods.
+    fn cents(&self, degree: i64) -> f64 {
+        let n = self.degrees.len() as i64;
+        let period = self.degrees[self.degrees.len() - 1];
+        let (periods, step) = (degree.div_euclid(n), degree.rem_euclid(n));
+        let within = if step == 0 {
+            0.0
+        } else {
+            self.degrees[step as usize - 1]
+        };
+        periods as f64 * period + within
+    }
+}
+
+impl KeyboardMapping {
+    /// Every key in order, one degree per key, with C4 on the first degree and A4 as the
+    /// reference: the usual layout for twelve-note scales.
+    pub fn standard() -> Self {
+        Self {
+            first_key: 0,
+            last_key: 127,
+            middle_key: 60,
+            reference_key: 69,
+            reference_frequency: 440.0,
+            octave_degree: 0,
+            pattern: Vec::new(),
+        }
+    }
+
+    /// Parses the contents of a `.kbm` file.
+    pub fn parse(text: &str) -> Result<Self, TuningError> {
+        let mut lines = data_lines(text).filter(|(_, line)| !line.is_empty());
+        let mut next = |what: &str| -> Result<(usize, String), TuningError> {
+            lines
+                .next()
+                .map(|(n, line)| (n, first_word(line).to_string()))
+                .ok_or_else(|| parse_error(text.lines().count(), format!("missing {what}")))
+        };
+        fn number<T: std::str::FromStr>(
+            (line, word): (usize, String),
+            what: &str,
+        ) -> Result<T, TuningError> {
+            word.parse()
+                .map_err(|_| parse_error(line, format!("bad {what}: {word}")))
+        }
+
+        let size: usize = number(next("map size")?, "map size")?;
+        let first_key: u8 = number(next("first key")?, "first key")?;
+        let last_key: u8 = number(next("last key")?, "last key")?;
+        let middle_key: u8 = number(next("middle key")?, "middle key")?;
+        let reference_key: u8 = number(next("reference key")?, "reference key")?;
+        let reference_frequency: f64 = number(next("reference frequency")?, "frequency")?;
+        let octave_degree: usize = number(next("octave degree")?, "octave degree")?;
+
+        let mut pattern = Vec::with_capacity(size);
+        for _ in 0..size {
+            // Scala allows the pattern to stop early; missing keys are silent
+            let Ok(entry) = next("key mapping") else {
+                pattern.resize(size, None);
+                break;
+            };
+            if entry.1.eq_ignore_ascii_case("x") {
+                pattern.push(None);
+            } else {
+                pattern.push(Some(number(entry, "scale degree")?));
+            }
+        }
+        if !reference_frequency.is_finite()
+            || reference_frequency <= 0.0
+            || first_key > last_key
+            || last_key > 127
+        {
+            return Err(parse_error(
+                1,
+                "reference frequency or key range out of bounds",
+            ));
+        }
+        Ok(Self {
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_frequency,
+            octave_degree,
+            pattern,
+        })
+    }
+
+    /// Scale degree played by `key`, counted from the middle key, or `None` if it is silent.
+    fn degree(&self, key: u8, scale: &Scale) -> Option<i64> {
+        if key < self.first_key || key > self.last_key {
+            return None;
+        }
+        let offset = key as i64 - self.middle_key as i64;
+        if self.pattern.is_empty() {
+            return Some(offset);
+        }
+        let size = self.pattern.len() as i64;
+        let octave = if self.octave_degree == 0 {
+            scale.degrees.len() as i64
+        } else {
+            self.octave_degree as i64
+        };
+        let degree = self.pattern[offset.rem_euclid(size) as usize]?;
+        Some(offset.div_euclid(size) * octave + degree as i64)
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        TuningPreset::EqualTemperament.tuning()
+    }
+}
+
+impl Tuning {
+    pub fn load_scl(path: &Path) -> Result<Scale, TuningError> {
+        Scale::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn load_kbm(path: &Path) -> Result<KeyboardMapping, TuningError> {
+        KeyboardMapping::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Swaps in a scale read from a `.scl` file, keeping the keyboard mapping.
+    pub fn set_scale(&mut self, name: &str, scale: Scale) {
+        self.scale_name = name.to_string();
+        self.scale = scale;
+        self.rename();
+    }
+
+    /// Swaps in a mapping read from a `.kbm` file, keeping the scale.
+    pub fn set_mapping(&mut self, name: &str, mapping: KeyboardMapping) {
+        if self.scale_name.is_empty() {
+            self.scale_name = self.name.clone();
+        }
+        self.mapping_name = Some(name.to_string());
+        self.mapping = mapping;
+        self.rename();
+    }
+
+    fn rename(&mut self) {
+        self.name = match &self.mapping_name {
+            Some(mapping) => format!("{} / {mapping}", self.scale_name),
+            None => self.scale_name.clone(),
+        };
+    }
+
+    /// Frequency of `key` when the reference key sounds at `reference_hz`, or `None` for a
+    /// key the mapping leaves silent.
+    pub fn key_frequency(&self, key: u8, reference_hz: f64) -> Option<f64> {
+        let degree = self.mapping.degree(key, &self.scale)?;
+        let reference = self
+            .mapping
+            .degree(self.mapping.reference_key, &self.scale)
+            .unwrap_or(0);
+        let cents = self.scale.cents(degree) - self.scale.cents(reference);
+        Some(reference_hz * 2f64.powf(cents / 1200.0))
+    }
+
+    /// Frequency of a possibly fractional key, gliding geometrically between neighbouring
+    /// keys, or `None` where it needs a key the mapping leaves silent.
+    pub fn frequency(&self, pitch: f32, reference_hz: f64) -> Option<f64> {
+        // Pitches past either end of the keyboard extrapolate from the outermost keys
+        let key = pitch.floor().clamp(0.0, 126.0);
+        let frac = (pitch - key) as f64;
+        let low = self.key_frequency(key as u8, reference_hz)?;
+        if frac == 0.0 {
+            return Some(low);
+        }
+        let high = self.key_frequency(key as u8 + 1, reference_hz)?;
+        Some(low * (high / low).powf(frac))
+    }
+}
+
+/// Built-in twelve-note temperaments, all starting on C and tuned from A4.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TuningPreset {
+    EqualTemperament,
+    JustIntonation,
+    Pythagorean,
+    QuarterCommaMeantone,
+    WerckmeisterIII,
+}
+
+impl TuningPreset {
+    pub const ALL: [TuningPreset; 5] = [
+        TuningPreset::EqualTemperament,
+        TuningPreset::JustIntonation,
+        TuningPreset::Pythagorean,
+        TuningPreset::QuarterCommaMeantone,
+        TuningPreset::WerckmeisterIII,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TuningPreset::EqualTemperament => "Equal temperament",
+            TuningPreset::JustIntonation => "Just intonation (5-limit)",
+            TuningPreset::Pythagorean => "Pythagorean",
+            TuningPreset::QuarterCommaMeantone => "Quarter-comma meantone",
+            TuningPreset::WerckmeisterIII => "Werckmeister III",
+        }
+    }
+
+    pub fn tuning(self) -> Tuning {
+        let ratios = |r: [(u32, u32); 12]| -> Vec<f64> {
+            r.iter()
+                .map(|&(n, d)| cents_of_ratio(n as f64 / d as f64))
+                .collect()
+        };
+        let degrees = match self {
+            TuningPreset::EqualTemperament => Scale::equal_temperament().degrees,
+            TuningPreset::JustIntonation => ratios([
+                (16, 15),
+                (9, 8),
+                (6, 5),
+                (5, 4),
+                (4, 3),
+                (45, 32),
+                (3, 2),
+                (8, 5),
+                (5, 3),
+                (9, 5),
+                (15, 8),
+                (2, 1),
+            ]),
+            TuningPreset::Pythagorean => ratios([
+                (256, 243),
+                (9, 8),
+                (32, 27),
+                (81, 64),
+                (4, 3),
+                (729, 512),
+                (3, 2),
+                (128, 81),
+                (27, 16),
+                (16, 9),
+                (243, 128),
+                (2, 1),
+            ]),
+            TuningPreset::QuarterCommaMeantone => vec![
+                76.049, 193.157, 310.265, 386.314, 503.422, 579.471, 696.578, 772.627, 889.735,
+                1006.843, 1082.892, 1200.0,
+            ],
+            TuningPreset::WerckmeisterIII => vec![
+                90.225, 192.180, 294.135, 390.225, 498.045, 588.270, 696.090, 792.180, 888.270,
+                996.090, 1092.180, 1200.0,
+            ],
+        };
+        Tuning {
+            name: self.label().to_string(),
+            scale_name: self.label().to_string(),
+            mapping_name: None,
+            scale: Scale {
+                description: self.label().to_string(),
+                degrees,
+            },
+            mapping: KeyboardMapping::standard(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error_line<T: fmt::Debug>(result: Result<T, TuningError>) -> usize {
+        match result {
+            Err(TuningError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn scale_skips_comments_and_keeps_description() {
+        let scale = Scale::parse(
+            "! meantone.scl\n\
+             !\n\
+             Quarter-comma meantone, abridged\n\
+             ! number of notes\n\
+             \x20 3\n\
+             !\n\
+             193.157 major second\n\
+             5/4\n\
+             2\n",
+        )
+        .unwrap();
+        assert_eq!(scale.description, "Quarter-comma meantone, abridged");
+        assert_eq!(scale.degrees.len(), 3);
+        assert_eq!(scale.degrees[0], 193.157);
+        assert!((scale.degrees[1] - 386.3137).abs() < 1e-3);
+        assert!((scale.degrees[2] - 1200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn scale_allows_a_blank_description() {
+        let scale = Scale::parse("\n1\n2/1\n").unwrap();
+        assert_eq!(scale.description, "");
+        assert_eq!(scale.degrees, [1200.0]);
+    }
+
+    #[test]
+    fn malformed_scales_are_errors() {
+        assert_eq!(parse_error_line(Scale::parse("")), 1);
+        assert_eq!(parse_error_line(Scale::parse("Bad count\nmany\n")), 2);
+        assert_eq!(
+            parse_error_line(Scale::parse("Bad pitch\n2\n100.0\n3/0\n")),
+            4
+        );
+        assert!(Scale::parse("Too short\n3\n100.0\n200.0\n").is_err());
+        assert!(Scale::parse("Empty\n0\n").is_err());
+    }
+
+    #[test]
+    fn mapping_reads_unmapped_keys() {
+        let mapping = KeyboardMapping::parse(
+            "! Seven white keys\n\
+             12\n0\n127\n60\n69\n440.0\n7\n\
+             ! Mapping\n\
+             0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n",
+        )
+        .unwrap();
+        assert_eq!(mapping.octave_degree, 7);
+        assert_eq!(mapping.pattern.len(), 12);
+        assert_eq!(&mapping.pattern[..4], [Some(0), None, Some(1), None]);
+    }
+
+    #[test]
+    fn short_mapping_pattern_leaves_the_rest_silent() {
+        let mapping = KeyboardMapping::parse("4\n0\n127\n60\n60\n261.6\n0\n0\n1\n").unwrap();
+        assert_eq!(mapping.pattern, [Some(0), Some(1), None, None]);
+    }
+
+    #[test]
+    fn malformed_mappings_are_errors() {
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n").is_err());
+        assert_eq!(
+            parse_error_line(KeyboardMapping::parse("1\n0\n127\n60\n69\nfast\n0\n0\n")),
+            6
+        );
+        assert_eq!(
+            parse_error_line(KeyboardMapping::parse("1\n0\n127\n60\n69\n440\n0\ny\n")),
+            8
+        );
+        assert!(KeyboardMapping::parse("0\n100\n20\n60\n69\n440\n0\n").is_err());
+    }
+
+    #[test]
+    fn key_frequency_follows_scale_and_mapping() {
+        let equal = Tuning::default();
+        assert!((equal.key_frequency(69, 440.0).unwrap() - 440.0).abs() < 1e-9);
+        assert!((equal.key_frequency(81, 440.0).unwrap() - 880.0).abs() < 1e-9);
+        assert!((equal.key_frequency(60, 440.0).unwrap() - 261.6256).abs() < 1e-3);
+
+        // Keys play the first and third of three degrees, so each pair of keys spans one
+        // period: 60 plays the first degree, 61 the third, 62 the next period's first
+        let mut tuning = Tuning::default();
+        tuning.set_scale(
+            "thirds",
+            Scale::parse("Thirds\n3\n5/4\n3/2\n2/1\n").unwrap(),
+        );
+        tuning.set_mapping(
+            "pairs",
+            KeyboardMapping::parse("2\n0\n127\n60\n60\n200\n0\n0\n2\n").unwrap(),
+        );
+        let frequency = |key| tuning.key_frequency(key, 200.0);
+        assert!((frequency(60).unwrap() - 200.0).abs() < 1e-9);
+        assert!((frequency(61).unwrap() - 300.0).abs() < 1e-9);
+        assert!((frequency(62).unwrap() - 400.0).abs() < 1e-9);
+        assert!((frequency(58).unwrap() - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn silent_keys_have_no_frequency() {
+        let mut tuning = Tuning::default();
+        tuning.set_mapping(
+            "half",
+            KeyboardMapping::parse("2\n48\n72\n60\n69\n440\n1\n0\nx\n").unwrap(),
+        );
+        assert!(tuning.key_frequency(61, 440.0).is_none());
+        assert!(tuning.key_frequency(40, 440.0).is_none());
+        assert!(tuning.key_frequency(62, 440.0).is_some());
+
+        // Bends glide only between sounding keys
+        assert!(tuning.frequency(62.0, 440.0).is_some());
+        assert!(tuning.frequency(61.0, 440.0).is_none());
+        assert!(tuning.frequency(62.5, 440.0).is_none());
+    }
+
+    #[test]
+    fn loading_mappings_does_not_stack_names() {
+        let mut tuning = Tuning::default();
+        let mapping = KeyboardMapping::standard();
+        tuning.set_mapping("first", mapping.clone());
+        tuning.set_mapping("second", mapping.clone());
+        assert_eq!(tuning.name, "Equal temperament / second");
+        tuning.set_scale("pelog", Scale::equal_temperament());
+        assert_eq!(tuning.name, "pelog / second");
+    }
+}